@@ -1,9 +1,16 @@
+use chrono::{DateTime, NaiveDateTime};
 use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
 use dotenv::dotenv;
 use gadget_lib::{
-    api::{ApiRedirect, RedirectList, UpdateRedirect},
-    prelude::RedirectModel,
-    AliasRedirect, Redirect,
+    api::{
+        ApiRedirect, RedirectList, ResolveBatch, ResolveBatchResult, SuggestionList, UpdateRedirect,
+    },
+    passthrough::QueryPassthrough,
+    pattern::AliasPattern,
+    prelude::{RedirectModel, RedirectOptions, RedirectStatus},
+    schedule::ScheduledChange,
+    visibility::Visibility,
+    weighted::{Selection, WeightedDestination},
 };
 use human_panic::setup_panic;
 use log::{debug, error, trace};
@@ -70,6 +77,17 @@ impl ApiOptions {
             builder = builder.use_rustls_tls().identity(identity).https_only(true);
         }
 
+        if self.auth.auth_type == AuthType::Token {
+            let token = self.auth.api_token()?;
+            let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+                .map_err(|_| CliError::TokenError("the token isn't a valid header".to_owned()))?;
+            value.set_sensitive(true);
+
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+            builder = builder.default_headers(headers);
+        }
+
         trace!("builder {:?}", builder);
 
         Ok(builder.build()?)
@@ -139,6 +157,53 @@ struct ApiAuth {
     /// Path to x509 CA if not a trusted root
     #[clap(name = "ca", long, global(true), value_parser, value_hint = clap::ValueHint::DirPath, requires="cert")]
     mtls_ca: Option<PathBuf>,
+
+    /// API token, instead of reading it from the token file
+    #[clap(
+        name = "token",
+        long,
+        global(true),
+        value_parser,
+        env("GADGET_API_TOKEN"),
+        hide_env_values(true)
+    )]
+    api_token: Option<String>,
+
+    /// File with the API token, `~/.config/gadget/token` by default
+    #[clap(name = "token-file", long, global(true), value_parser, value_hint = clap::ValueHint::FilePath, env("GADGET_API_TOKEN_FILE"))]
+    api_token_file: Option<PathBuf>,
+}
+
+impl ApiAuth {
+    /// The API token from `--token`, or else from the token file.
+    fn api_token(&self) -> Result<String, CliError> {
+        if let Some(token) = &self.api_token {
+            return Ok(token.trim().to_owned());
+        }
+
+        let path = match &self.api_token_file {
+            Some(path) => path.clone(),
+            None => std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+                })
+                .ok_or_else(|| {
+                    CliError::TokenError("there is no --token or --token-file".to_owned())
+                })?
+                .join("gadget")
+                .join("token"),
+        };
+        debug!("Loading API token from {:?}", path);
+        let token = std::fs::read_to_string(&path).map_err(|e| {
+            CliError::TokenError(format!("unable to read {}: {}", path.display(), e))
+        })?;
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(CliError::TokenError(format!("{} is empty", path.display())));
+        }
+        Ok(token.to_owned())
+    }
 }
 
 #[derive(ValueEnum, PartialEq, Debug, Clone)]
@@ -146,12 +211,14 @@ enum AuthType {
     None,
     #[clap(name = "mtls", alias = "x509")]
     MutualAuth,
+    /// API token from the server, sent as a bearer token
+    Token,
 }
 
 #[derive(Subcommand, Debug)]
 enum CommandOptions {
     /// List all the redirects avaliable
-    List,
+    List(ListArgs),
     /// Get a single redirect
     Get(GetArgs),
     /// Delete a redirect
@@ -160,6 +227,10 @@ enum CommandOptions {
     Update(TupleArgs),
     /// Create a redirect
     Create(TupleArgs),
+    /// Create a redirect with a generated alias
+    Shorten(ShortenArgs),
+    /// Find redirects with an alias close to the query
+    Suggest(SuggestArgs),
 }
 
 #[derive(Args, Debug)]
@@ -171,6 +242,255 @@ struct TupleArgs {
     /// Where the redirect will be send to
     #[clap(long, value_parser)]
     destination: String,
+
+    #[clap(flatten)]
+    options: RedirectOptionArgs,
+}
+
+#[derive(Args, Debug)]
+struct ShortenArgs {
+    /// Where the redirect will be send to
+    #[clap(value_parser)]
+    destination: String,
+
+    #[clap(flatten)]
+    options: RedirectOptionArgs,
+}
+
+#[derive(Args, Debug)]
+struct RedirectOptionArgs {
+    /// Append the rest of the path to the destination when the alias matches by prefix
+    #[clap(long, value_parser)]
+    path_passthrough: Option<bool>,
+
+    /// How the alias is matched
+    #[clap(long, value_enum)]
+    pattern: Option<PatternArg>,
+
+    /// Patterns are tried lowest priority first
+    #[clap(long, value_parser, allow_hyphen_values(true))]
+    priority: Option<i32>,
+
+    /// What the redirect is for, shown when it's previewed
+    #[clap(long, value_parser)]
+    description: Option<String>,
+
+    /// Status code to redirect with: 301, 302, 307 or 308
+    #[clap(long, value_parser = parse_status)]
+    status: Option<RedirectStatus>,
+
+    /// How many seconds clients may cache the redirect for
+    #[clap(long, value_parser)]
+    max_age: Option<u32>,
+
+    /// What to do with the query string of the incoming link
+    #[clap(long, value_enum)]
+    query_passthrough: Option<QueryPassthroughArg>,
+
+    /// When the redirect stops working, as RFC 3339 like 2022-08-01T12:00:00Z
+    #[clap(long, value_parser = parse_expires_at)]
+    expires_at: Option<NaiveDateTime>,
+
+    /// How many times the redirect can be used before it stops working
+    #[clap(long, value_parser)]
+    max_uses: Option<u64>,
+
+    /// A destination that takes over at a time, as <RFC 3339 time>=<url>. Add /<seconds>
+    /// after the time to repeat it, like 2022-08-01T09:00:00Z/1209600=https://example.com
+    #[clap(long, value_parser = parse_scheduled_change)]
+    schedule: Vec<ScheduledChange>,
+
+    /// A destination to split the traffic with, as <weight>=<url>
+    #[clap(long = "split", value_parser = parse_weighted_destination)]
+    destinations: Vec<WeightedDestination>,
+
+    /// How requests are given one of the split destinations
+    #[clap(long, value_enum)]
+    selection: Option<SelectionArg>,
+
+    /// Only let this user, and whoever created the redirect, see it
+    #[clap(long = "restrict-to-user", value_parser)]
+    restrict_to_users: Vec<String>,
+
+    /// Only let members of this group, and whoever created the redirect, see it
+    #[clap(long = "restrict-to-group", value_parser)]
+    restrict_to_groups: Vec<String>,
+
+    /// Let everyone see the redirect again
+    #[clap(long, action, conflicts_with_all(&["restrict-to-users", "restrict-to-groups"]))]
+    public: bool,
+}
+
+#[derive(ValueEnum, PartialEq, Debug, Clone)]
+enum SelectionArg {
+    Random,
+    Sticky,
+}
+
+impl From<&SelectionArg> for Selection {
+    fn from(arg: &SelectionArg) -> Self {
+        match arg {
+            SelectionArg::Random => Selection::Random,
+            SelectionArg::Sticky => Selection::Sticky,
+        }
+    }
+}
+
+#[derive(ValueEnum, PartialEq, Debug, Clone)]
+enum QueryPassthroughArg {
+    Merge,
+    Override,
+    Ignore,
+}
+
+impl From<&QueryPassthroughArg> for QueryPassthrough {
+    fn from(arg: &QueryPassthroughArg) -> Self {
+        match arg {
+            QueryPassthroughArg::Merge => QueryPassthrough::Merge,
+            QueryPassthroughArg::Override => QueryPassthrough::Override,
+            QueryPassthroughArg::Ignore => QueryPassthrough::Ignore,
+        }
+    }
+}
+
+fn parse_status(status: &str) -> Result<RedirectStatus, String> {
+    let code: u16 = status
+        .parse()
+        .map_err(|_| format!("{} isn't a number", status))?;
+    RedirectStatus::try_from(code)
+}
+
+fn parse_expires_at(expires_at: &str) -> Result<NaiveDateTime, String> {
+    DateTime::parse_from_rfc3339(expires_at)
+        .map(|x| x.naive_utc())
+        .map_err(|e| format!("{} isn't an RFC 3339 time: {}", expires_at, e))
+}
+
+fn parse_scheduled_change(change: &str) -> Result<ScheduledChange, String> {
+    let (when, destination) = change
+        .split_once('=')
+        .ok_or_else(|| format!("{} isn't <time>=<url>", change))?;
+    let (starts_at, every) = match when.split_once('/') {
+        Some((starts_at, every)) => (
+            starts_at,
+            Some(
+                every
+                    .parse()
+                    .map_err(|_| format!("{} isn't a number of seconds", every))?,
+            ),
+        ),
+        None => (when, None),
+    };
+
+    Ok(ScheduledChange {
+        destination: destination.to_owned(),
+        starts_at: parse_expires_at(starts_at)?,
+        every,
+    })
+}
+
+fn parse_weighted_destination(split: &str) -> Result<WeightedDestination, String> {
+    let (weight, destination) = split
+        .split_once('=')
+        .ok_or_else(|| format!("{} isn't <weight>=<url>", split))?;
+
+    Ok(WeightedDestination {
+        destination: destination.to_owned(),
+        weight: weight
+            .parse()
+            .map_err(|_| format!("{} isn't a weight", weight))?,
+    })
+}
+
+impl RedirectOptionArgs {
+    fn is_empty(&self) -> bool {
+        self.path_passthrough.is_none()
+            && self.pattern.is_none()
+            && self.priority.is_none()
+            && self.description.is_none()
+            && self.status.is_none()
+            && self.max_age.is_none()
+            && self.query_passthrough.is_none()
+            && self.expires_at.is_none()
+            && self.max_uses.is_none()
+            && self.schedule.is_empty()
+            && self.destinations.is_empty()
+            && self.selection.is_none()
+            && self.restrict_to_users.is_empty()
+            && self.restrict_to_groups.is_empty()
+            && !self.public
+    }
+
+    fn apply(&self, options: &mut RedirectOptions) {
+        if let Some(path_passthrough) = self.path_passthrough {
+            options.path_passthrough = path_passthrough;
+        }
+        if let Some(pattern) = &self.pattern {
+            options.pattern = pattern.into();
+        }
+        if let Some(priority) = self.priority {
+            options.priority = priority;
+        }
+        if let Some(description) = &self.description {
+            options.description = Some(description.clone());
+        }
+        if let Some(status) = self.status {
+            options.status = status;
+        }
+        if let Some(max_age) = self.max_age {
+            options.max_age = Some(max_age);
+        }
+        if let Some(query_passthrough) = &self.query_passthrough {
+            options.query_passthrough = query_passthrough.into();
+        }
+        if let Some(expires_at) = self.expires_at {
+            options.expires_at = Some(expires_at);
+        }
+        if let Some(max_uses) = self.max_uses {
+            options.max_uses = Some(max_uses);
+        }
+        if !self.schedule.is_empty() {
+            options.schedule = self.schedule.clone();
+        }
+        if !self.destinations.is_empty() {
+            options.destinations = self.destinations.clone();
+        }
+        if let Some(selection) = &self.selection {
+            options.selection = selection.into();
+        }
+        if self.public {
+            options.visibility = Visibility::Public;
+        } else if !self.restrict_to_users.is_empty() || !self.restrict_to_groups.is_empty() {
+            options.visibility = Visibility::Restricted {
+                users: self.restrict_to_users.clone(),
+                groups: self.restrict_to_groups.clone(),
+            };
+        }
+    }
+}
+
+#[derive(ValueEnum, PartialEq, Debug, Clone)]
+enum PatternArg {
+    Exact,
+    Glob,
+    Regex,
+}
+
+impl From<&PatternArg> for AliasPattern {
+    fn from(arg: &PatternArg) -> Self {
+        match arg {
+            PatternArg::Exact => AliasPattern::Exact,
+            PatternArg::Glob => AliasPattern::Glob,
+            PatternArg::Regex => AliasPattern::Regex,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Include your own personal redirects, the ones under ~username/
+    #[clap(long, action)]
+    personal: bool,
 }
 
 #[derive(Args, Debug)]
@@ -184,6 +504,13 @@ struct GetArgs {
     options: Vec<String>,
 }
 
+#[derive(Args, Debug)]
+struct SuggestArgs {
+    /// Alias to look for
+    #[clap(value_parser)]
+    query: String,
+}
+
 #[derive(Args, Debug)]
 struct DeleteArgs {
     /// Name of the redirect
@@ -200,6 +527,8 @@ pub enum CliError {
     },
     #[error("Certificate {0} does not exist or cannot be read")]
     CertificateError(String),
+    #[error("Unable to use the API token, {0}")]
+    TokenError(String),
     #[error(transparent)]
     ReqwestError {
         #[from]
@@ -233,63 +562,116 @@ fn main() {
 #[tokio::main]
 async fn run_command(opts: Cli) -> Result<(), CliError> {
     match opts.command {
-        CommandOptions::List => run_list(&opts.api_options).await?,
+        CommandOptions::List(args) => run_list(&args, &opts.api_options).await?,
         CommandOptions::Get(args) => run_get(&args, &opts.api_options).await?,
         CommandOptions::Create(args) => run_create(&args, &opts.api_options).await?,
+        CommandOptions::Shorten(args) => run_shorten(&args, &opts.api_options).await?,
         CommandOptions::Delete(args) => run_delete(&args, &opts.api_options).await?,
         CommandOptions::Update(args) => run_update(&args, &opts.api_options).await?,
+        CommandOptions::Suggest(args) => run_suggest(&args, &opts.api_options).await?,
     }
 
     Ok(())
 }
 
-async fn run_list(api_opts: &ApiOptions) -> Result<(), CliError> {
+async fn run_list(args: &ListArgs, api_opts: &ApiOptions) -> Result<(), CliError> {
+    let path = if args.personal {
+        "/_api/redirect?personal=true"
+    } else {
+        "/_api/redirect"
+    };
     let body: RedirectList = api_opts
-        .make_request::<(), _>("/_api/redirect", Method::GET, None)
+        .make_request::<(), _>(path, Method::GET, None)
         .await?;
     crate::output::show_redirects(body.redirects);
 
     Ok(())
 }
 
+/// The API path for a redirect. The `/` in personal aliases like `~alice/notes` are encoded.
+fn redirect_path(alias: &str) -> String {
+    format!("/_api/redirect/{}", alias.replace('/', "%2F"))
+}
+
 async fn run_get(args: &GetArgs, api_opts: &ApiOptions) -> Result<(), CliError> {
     let body: ApiRedirect = api_opts
-        .make_request::<(), _>(&format!("/_api/redirect/{}", args.alias), Method::GET, None)
+        .make_request::<(), _>(&redirect_path(&args.alias), Method::GET, None)
         .await?;
 
     println!("Redirect target: {}", body.destination);
-    let redirect = AliasRedirect::from(body);
-
-    for test_dest in &args.options {
-        println!(
-            "'{}/{}' will redirect to {}",
-            &args.alias,
-            &test_dest,
-            redirect.evaluate(test_dest)
-        );
+    if args.options.is_empty() {
+        return Ok(());
+    }
+
+    let batch = ResolveBatch {
+        queries: args
+            .options
+            .iter()
+            .map(|test_dest| format!("{} {}", args.alias, test_dest))
+            .collect(),
+    };
+    let body: ResolveBatchResult = api_opts
+        .make_request("/_api/resolve", Method::POST, Some(&batch))
+        .await?;
+
+    for result in body.results {
+        match (result.resolved, result.error) {
+            (Some(resolved), _) => {
+                println!(
+                    "'{}' will redirect to {}",
+                    result.query, resolved.destination
+                );
+                for warning in resolved.warnings {
+                    println!("  warning: {}", warning);
+                }
+            }
+            (None, Some(error)) => println!("'{}' failed: {}", result.query, error),
+            (None, None) => println!("'{}' doesn't match a redirect", result.query),
+        }
     }
     Ok(())
 }
 
-async fn run_delete(args: &DeleteArgs, api_opts: &ApiOptions) -> Result<(), CliError> {
-    api_opts
+async fn run_suggest(args: &SuggestArgs, api_opts: &ApiOptions) -> Result<(), CliError> {
+    let body: SuggestionList = api_opts
         .make_request::<(), _>(
-            &format!("/_api/redirect/{}", args.alias),
-            Method::DELETE,
+            &format!("/_api/suggest?q={}", urlencoding::encode(&args.query)),
+            Method::GET,
             None,
         )
         .await?;
+
+    if body.suggestions.is_empty() {
+        println!("No redirects look like {}", body.query);
+    }
+    for suggestion in body.suggestions {
+        println!("{} => {}", suggestion.alias, suggestion.destination);
+    }
+    Ok(())
+}
+
+async fn run_delete(args: &DeleteArgs, api_opts: &ApiOptions) -> Result<(), CliError> {
+    api_opts
+        .make_request::<(), _>(&redirect_path(&args.alias), Method::DELETE, None)
+        .await?;
     Ok(())
 }
 
 async fn run_create(args: &TupleArgs, api_opts: &ApiOptions) -> Result<(), CliError> {
+    let mut options = RedirectOptions::default();
+    args.options.apply(&mut options);
+
     let redirect = ApiRedirect {
         alias: args.alias.clone(),
         destination: args.destination.clone(),
         created_by: None,
+        last_edited_by: None,
+        options,
+        hits: 0,
+        short_url: None,
     };
 
-    let body: RedirectModel = api_opts
+    let body: ApiRedirect = api_opts
         .make_request("/_api/redirect", Method::POST, Some(&redirect))
         .await?;
 
@@ -297,18 +679,48 @@ async fn run_create(args: &TupleArgs, api_opts: &ApiOptions) -> Result<(), CliEr
     Ok(())
 }
 
+async fn run_shorten(args: &ShortenArgs, api_opts: &ApiOptions) -> Result<(), CliError> {
+    let mut options = RedirectOptions::default();
+    args.options.apply(&mut options);
+
+    let redirect = ApiRedirect {
+        alias: String::new(),
+        destination: args.destination.clone(),
+        created_by: None,
+        last_edited_by: None,
+        options,
+        hits: 0,
+        short_url: None,
+    };
+
+    let body: ApiRedirect = api_opts
+        .make_request("/_api/redirect", Method::POST, Some(&redirect))
+        .await?;
+
+    println!("{}", body.short_url.unwrap_or(body.alias));
+    Ok(())
+}
+
 async fn run_update(args: &TupleArgs, api_opts: &ApiOptions) -> Result<(), CliError> {
+    let options = if args.options.is_empty() {
+        None
+    } else {
+        let current: ApiRedirect = api_opts
+            .make_request::<(), _>(&redirect_path(&args.alias), Method::GET, None)
+            .await?;
+        let mut options = current.options;
+        args.options.apply(&mut options);
+        Some(options)
+    };
+
     let redirect = UpdateRedirect {
         destination: args.destination.clone(),
         created_by: None,
+        options,
     };
 
     let body: RedirectModel = api_opts
-        .make_request(
-            &format!("/_api/redirect/{}", args.alias),
-            Method::PUT,
-            Some(&redirect),
-        )
+        .make_request(&redirect_path(&args.alias), Method::PUT, Some(&redirect))
         .await?;
     debug!("Response from worker: {:?}", body);
     println!("Updated {} to point at {}", body.alias, body.destination);
@@ -1,8 +1,14 @@
 use std::path::PathBuf;
 
 use crate::backend::prelude::*;
+use crate::namespace::DEFAULT_NAMESPACE;
+use crate::normalize::AliasNormalization;
+use crate::pattern::PatternMatch;
 use crate::prelude::LibResult;
+use crate::token::ApiTokenModel;
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use tracing::{error, warn};
 
 pub struct JsonBackend {
@@ -10,9 +16,17 @@ pub struct JsonBackend {
     file_path: PathBuf,
 }
 
+/// The default namespace is at the top of the file, every other namespace has its own section
+/// in `namespaces`.
 #[derive(Serialize, Deserialize)]
 struct JsonFile {
-    redirects: Vec<RedirectModel>,
+    #[serde(flatten)]
+    default: NamespaceData,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    namespaces: BTreeMap<String, NamespaceData>,
+    /// Only the hashes of the tokens.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tokens: Vec<ApiTokenModel>,
 }
 
 impl JsonFile {
@@ -27,9 +41,11 @@ impl JsonBackend {
         let backend = if !file_path.exists() {
             warn!("{:?} does not exist, creating new file.", file_path);
             let backend = InMemoryBackend::new(Default::default());
-            let redirects = backend.get_internal_model()?;
-
-            let json_file = JsonFile { redirects };
+            let json_file = JsonFile {
+                default: Default::default(),
+                namespaces: BTreeMap::new(),
+                tokens: Vec::new(),
+            };
             json_file.save(&file_path)?;
             backend
         } else {
@@ -41,7 +57,12 @@ impl JsonBackend {
                         panic!();
                     }
                 };
-            InMemoryBackend::new(json_file.redirects)
+            let mut backend = InMemoryBackend::new(Vec::new())
+                .with_namespace(DEFAULT_NAMESPACE, json_file.default);
+            for (namespace, data) in json_file.namespaces {
+                backend = backend.with_namespace(&namespace, data);
+            }
+            backend.with_tokens(json_file.tokens)
         };
 
         Ok(JsonBackend {
@@ -50,53 +71,168 @@ impl JsonBackend {
         })
     }
 
+    pub fn with_normalization(mut self, normalization: AliasNormalization) -> Self {
+        self.in_memory = self.in_memory.with_normalization(normalization);
+        self
+    }
+
     fn save(&self) -> LibResult<()> {
-        let redirects = self.in_memory.get_internal_model().unwrap();
+        let mut namespaces = self.in_memory.get_internal_namespaces()?;
+        let default = namespaces.remove(DEFAULT_NAMESPACE).unwrap_or_default();
 
-        let json_file = JsonFile { redirects };
+        let json_file = JsonFile {
+            default,
+            namespaces,
+            tokens: self.in_memory.get_internal_tokens()?,
+        };
         json_file.save(&self.file_path)?;
         Ok(())
     }
 }
 
 impl<'a> Backend<'a> for JsonBackend {
-    fn get_redirect(&self, redirect_ref: &str) -> LibResult<Option<RedirectModel>> {
-        self.in_memory.get_redirect(redirect_ref)
+    fn get_redirect(
+        &self,
+        namespace: &str,
+        redirect_ref: &str,
+    ) -> LibResult<Option<RedirectModel>> {
+        self.in_memory.get_redirect(namespace, redirect_ref)
+    }
+
+    fn find_pattern(&self, namespace: &str, alias: &str) -> LibResult<Option<PatternMatch>> {
+        self.in_memory.find_pattern(namespace, alias)
+    }
+
+    fn normalization(&self) -> AliasNormalization {
+        self.in_memory.normalization()
+    }
+
+    fn alias_collisions(&self, namespace: &str) -> LibResult<Vec<Vec<String>>> {
+        self.in_memory.alias_collisions(namespace)
     }
 
     fn create_redirect(
         &self,
+        namespace: &str,
         new_alias: &str,
         new_destination: &str,
+        options: &RedirectOptions,
         username: &str,
     ) -> LibResult<RedirectModel> {
-        let result = self
-            .in_memory
-            .create_redirect(new_alias, new_destination, username);
+        let result = self.in_memory.create_redirect(
+            namespace,
+            new_alias,
+            new_destination,
+            options,
+            username,
+        );
         self.save()?;
         result
     }
 
     fn update_redirect(
         &self,
+        namespace: &str,
         redirect_ref: &str,
         new_dest: &str,
+        options: Option<&RedirectOptions>,
         username: &str,
     ) -> LibResult<RedirectModel> {
-        let result = self
-            .in_memory
-            .update_redirect(redirect_ref, new_dest, username);
+        let result =
+            self.in_memory
+                .update_redirect(namespace, redirect_ref, new_dest, options, username);
         self.save()?;
         result
     }
 
-    fn delete_redirect(&self, redirect_ref: &str) -> LibResult<usize> {
-        let result = self.in_memory.delete_redirect(redirect_ref);
+    fn delete_redirect(&self, namespace: &str, redirect_ref: &str) -> LibResult<usize> {
+        let result = self.in_memory.delete_redirect(namespace, redirect_ref);
         self.save()?;
         result
     }
 
-    fn get_all(&self, page: u64, limit: usize) -> LibResult<Vec<RedirectModel>> {
-        self.in_memory.get_all(page, limit)
+    fn record_hit(&self, namespace: &str, redirect_ref: &str) -> LibResult<()> {
+        // Writing the file on every use would be expensive, the count is saved with the next change.
+        self.in_memory.record_hit(namespace, redirect_ref)?;
+
+        // Unless the uses are limited, then a restart mustn't hand out more of them.
+        if let Some(redirect) = self.in_memory.get_redirect(namespace, redirect_ref)? {
+            if redirect.options.max_uses.is_some() {
+                self.save()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn use_redirect(
+        &self,
+        namespace: &str,
+        redirect_ref: &str,
+        now: NaiveDateTime,
+    ) -> LibResult<bool> {
+        let used = self.in_memory.use_redirect(namespace, redirect_ref, now)?;
+
+        // Like with `record_hit`, only limited uses are saved right away.
+        if let Some(redirect) = self.in_memory.get_redirect(namespace, redirect_ref)? {
+            if used && redirect.options.max_uses.is_some() {
+                self.save()?;
+            }
+        }
+        Ok(used)
+    }
+
+    fn get_all(&self, namespace: &str, page: u64, limit: usize) -> LibResult<Vec<RedirectModel>> {
+        self.in_memory.get_all(namespace, page, limit)
+    }
+
+    fn sweep_expired(&self, namespace: &str, now: NaiveDateTime) -> LibResult<Vec<RedirectModel>> {
+        let swept = self.in_memory.sweep_expired(namespace, now)?;
+        if !swept.is_empty() {
+            self.save()?;
+        }
+        Ok(swept)
+    }
+
+    fn record_scheduled_changes(
+        &self,
+        namespace: &str,
+        since: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> LibResult<Vec<HistoryEntry>> {
+        let switches = self
+            .in_memory
+            .record_scheduled_changes(namespace, since, now)?;
+        if !switches.is_empty() {
+            self.save()?;
+        }
+        Ok(switches)
+    }
+
+    fn get_history(
+        &self,
+        namespace: &str,
+        page: u64,
+        limit: usize,
+    ) -> LibResult<Vec<HistoryEntry>> {
+        self.in_memory.get_history(namespace, page, limit)
+    }
+
+    fn namespaces(&self) -> LibResult<Vec<String>> {
+        self.in_memory.namespaces()
+    }
+
+    fn get_tokens(&self) -> LibResult<Vec<ApiTokenModel>> {
+        self.in_memory.get_tokens()
+    }
+
+    fn create_token(&self, token: &ApiTokenModel) -> LibResult<()> {
+        self.in_memory.create_token(token)?;
+        self.save()
+    }
+
+    fn delete_token(&self, token_id: &str) -> LibResult<usize> {
+        let deleted = self.in_memory.delete_token(token_id)?;
+        self.save()?;
+        Ok(deleted)
     }
 }
@@ -1,55 +1,238 @@
 use crate::backend::prelude::*;
+use crate::namespace::DEFAULT_NAMESPACE;
+use crate::normalize::AliasNormalization;
+use crate::pattern::{compile_pattern, PatternMatch, PatternSet};
 use crate::prelude::{GadgetLibError, LibResult};
+use crate::resolve::find_alias_loop;
+use crate::schedule::{active_destination, check_schedule, latest_change};
+use crate::token::ApiTokenModel;
+use chrono::NaiveDateTime;
+use std::collections::BTreeMap;
 use std::sync::{Arc, RwLock};
 
 pub struct InMemoryBackend {
-    storage: Arc<RwLock<Vec<RedirectModel>>>,
+    namespaces: Arc<RwLock<BTreeMap<String, NamespaceStore>>>,
+    tokens: Arc<RwLock<Vec<ApiTokenModel>>>,
+    normalization: AliasNormalization,
+}
+
+/// One namespace, with its pattern redirects compiled.
+#[derive(Default)]
+struct NamespaceStore {
+    redirects: Vec<RedirectModel>,
+    history: Vec<HistoryEntry>,
+    patterns: PatternSet,
+}
+
+impl NamespaceStore {
+    fn new(data: NamespaceData) -> Self {
+        NamespaceStore {
+            patterns: PatternSet::new(&data.redirects),
+            redirects: data.redirects,
+            history: data.history,
+        }
+    }
+
+    /// Recompile the pattern redirects, needs to happen after every change to the redirects.
+    fn refresh_patterns(&mut self) {
+        self.patterns = PatternSet::new(&self.redirects);
+    }
 }
 
 impl InMemoryBackend {
+    /// A backend with `redirects` in the [`DEFAULT_NAMESPACE`].
     pub fn new(redirects: Vec<RedirectModel>) -> Self {
         Self {
-            storage: Arc::new(RwLock::new(redirects)),
+            namespaces: Default::default(),
+            tokens: Default::default(),
+            normalization: Default::default(),
+        }
+        .with_namespace(
+            DEFAULT_NAMESPACE,
+            NamespaceData {
+                redirects,
+                history: Vec::new(),
+            },
+        )
+    }
+
+    pub fn with_normalization(mut self, normalization: AliasNormalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Sets the history of the [`DEFAULT_NAMESPACE`].
+    pub fn with_history(self, history: Vec<HistoryEntry>) -> Self {
+        if let Ok(mut namespaces) = self.namespaces.write() {
+            namespaces
+                .entry(DEFAULT_NAMESPACE.to_owned())
+                .or_default()
+                .history = history;
+        }
+        self
+    }
+
+    pub fn with_namespace(self, namespace: &str, data: NamespaceData) -> Self {
+        if let Ok(mut namespaces) = self.namespaces.write() {
+            namespaces.insert(namespace.to_owned(), NamespaceStore::new(data));
         }
+        self
+    }
+
+    pub fn with_tokens(self, tokens: Vec<ApiTokenModel>) -> Self {
+        if let Ok(mut existing) = self.tokens.write() {
+            *existing = tokens;
+        }
+        self
+    }
+
+    /// Index of the redirect `redirect_ref` refers to, either by public ref or by alias.
+    /// An alias that is exactly the same is preferred over one that is only the same after normalization.
+    fn position(&self, redirects: &[RedirectModel], redirect_ref: &str) -> Option<usize> {
+        redirects
+            .iter()
+            .position(|redirect| {
+                redirect.public_ref == redirect_ref || redirect.alias == redirect_ref
+            })
+            .or_else(|| {
+                redirects
+                    .iter()
+                    .position(|redirect| self.normalization.is_same(&redirect.alias, redirect_ref))
+            })
     }
 
+    /// The redirects in the [`DEFAULT_NAMESPACE`].
     pub fn get_internal_model(&self) -> LibResult<Vec<RedirectModel>> {
-        Ok(self.storage.read()?.clone())
+        Ok(self
+            .namespaces
+            .read()?
+            .get(DEFAULT_NAMESPACE)
+            .map(|store| store.redirects.clone())
+            .unwrap_or_default())
+    }
+
+    /// The history of the [`DEFAULT_NAMESPACE`].
+    pub fn get_internal_history(&self) -> LibResult<Vec<HistoryEntry>> {
+        Ok(self
+            .namespaces
+            .read()?
+            .get(DEFAULT_NAMESPACE)
+            .map(|store| store.history.clone())
+            .unwrap_or_default())
+    }
+
+    /// Every namespace, including the default one.
+    pub fn get_internal_namespaces(&self) -> LibResult<BTreeMap<String, NamespaceData>> {
+        Ok(self
+            .namespaces
+            .read()?
+            .iter()
+            .map(|(name, store)| {
+                let data = NamespaceData {
+                    redirects: store.redirects.clone(),
+                    history: store.history.clone(),
+                };
+                (name.clone(), data)
+            })
+            .collect())
+    }
+
+    pub fn get_internal_tokens(&self) -> LibResult<Vec<ApiTokenModel>> {
+        Ok(self.tokens.read()?.clone())
+    }
+
+    fn check_for_loop(
+        &self,
+        redirects: &[RedirectModel],
+        alias: &str,
+        destination: &str,
+    ) -> LibResult<()> {
+        let lookup = |target: &str| {
+            self.position(redirects, target)
+                .map(|index| redirects[index].destination.clone())
+        };
+
+        match find_alias_loop(alias, destination, lookup) {
+            Some(chain) => Err(GadgetLibError::AliasLoop(chain.join(" -> "))),
+            None => Ok(()),
+        }
     }
 }
 
 impl<'a> super::Backend<'a> for InMemoryBackend {
     #[tracing::instrument(skip(self))]
-    fn get_redirect(&self, redirect_ref: &str) -> LibResult<Option<RedirectModel>> {
-        let vec = self.storage.read()?;
-        if let Some(dest) = vec
-            .iter()
-            .find(|redirect| redirect.public_ref == redirect_ref || redirect.alias == redirect_ref)
-        {
-            Ok(Some(dest.clone()))
-        } else {
-            Ok(None)
-        }
+    fn get_redirect(
+        &self,
+        namespace: &str,
+        redirect_ref: &str,
+    ) -> LibResult<Option<RedirectModel>> {
+        let namespaces = self.namespaces.read()?;
+        Ok(namespaces.get(namespace).and_then(|store| {
+            self.position(&store.redirects, redirect_ref)
+                .map(|index| store.redirects[index].clone())
+        }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn find_pattern(&self, namespace: &str, alias: &str) -> LibResult<Option<PatternMatch>> {
+        let namespaces = self.namespaces.read()?;
+        Ok(namespaces
+            .get(namespace)
+            .and_then(|store| store.patterns.find(alias)))
+    }
+
+    fn normalization(&self) -> AliasNormalization {
+        self.normalization
+    }
+
+    fn alias_collisions(&self, namespace: &str) -> LibResult<Vec<Vec<String>>> {
+        let namespaces = self.namespaces.read()?;
+        Ok(namespaces
+            .get(namespace)
+            .map(|store| self.normalization.find_collisions(&store.redirects))
+            .unwrap_or_default())
     }
 
     #[tracing::instrument(skip(self))]
     fn create_redirect(
         &self,
+        namespace: &str,
         new_alias: &str,
         new_destination: &str,
+        options: &RedirectOptions,
         username: &str,
     ) -> LibResult<RedirectModel> {
-        let mut vec = self.storage.write()?;
-        if vec.iter().any(|redirect| redirect.alias == new_alias) {
-            return Err(GadgetLibError::RedirectExists(
-                "Alias already exists".to_string(),
-            ));
+        let mut namespaces = self.namespaces.write()?;
+        let store = namespaces.entry(namespace.to_owned()).or_default();
+        if let Some(existing) = store
+            .redirects
+            .iter()
+            .find(|redirect| self.normalization.is_same(&redirect.alias, new_alias))
+        {
+            return Err(GadgetLibError::RedirectExists(existing.alias.clone()));
         }
 
-        let id = vec.iter().map(|x| x.redirect_id).max().unwrap_or(0) + 1;
+        self.check_for_loop(&store.redirects, new_alias, new_destination)?;
+        compile_pattern(new_alias, options.pattern)?;
+        check_schedule(&options.schedule)?;
+
+        let id = store
+            .redirects
+            .iter()
+            .map(|x| x.redirect_id)
+            .max()
+            .unwrap_or(0)
+            + 1;
 
-        let model = RedirectModel::new(id, new_alias, new_destination, Some(username.to_string()));
-        vec.push(model.clone());
+        let model = RedirectModel::new(
+            id,
+            new_alias,
+            new_destination,
+            options,
+            Some(username.to_string()),
+        );
+        store.redirects.push(model.clone());
+        store.refresh_patterns();
 
         Ok(model)
     }
@@ -57,44 +240,409 @@ impl<'a> super::Backend<'a> for InMemoryBackend {
     #[tracing::instrument(skip(self))]
     fn update_redirect(
         &self,
+        namespace: &str,
         redirect_ref: &str,
         new_dest: &str,
+        options: Option<&RedirectOptions>,
         username: &str,
     ) -> LibResult<RedirectModel> {
-        let mut vec = self.storage.write()?;
-        for i in 0..vec.len() {
-            if vec[i].public_ref == redirect_ref || vec[i].alias == redirect_ref {
-                vec[i].set_destination(new_dest);
-                vec[i].update_username(Some(username));
-                return Ok(vec[i].clone());
+        let mut namespaces = self.namespaces.write()?;
+        let store = namespaces.get_mut(namespace);
+        let (store, i) = match store {
+            Some(store) => match self.position(&store.redirects, redirect_ref) {
+                Some(i) => (store, i),
+                None => {
+                    return Err(GadgetLibError::RedirectDoesNotExists(
+                        redirect_ref.to_string(),
+                    ))
+                }
+            },
+            None => {
+                return Err(GadgetLibError::RedirectDoesNotExists(
+                    redirect_ref.to_string(),
+                ))
             }
+        };
+
+        let vec = &mut store.redirects;
+        self.check_for_loop(vec, &vec[i].alias, new_dest)?;
+        if let Some(options) = options {
+            compile_pattern(&vec[i].alias, options.pattern)?;
+            check_schedule(&options.schedule)?;
+            vec[i].set_options(options);
         }
-        Err(GadgetLibError::RedirectDoesNotExists(
-            redirect_ref.to_string(),
-        ))
+        vec[i].set_destination(new_dest);
+        vec[i].set_last_edited_by(Some(username));
+        let updated = vec[i].clone();
+        store.refresh_patterns();
+        Ok(updated)
     }
 
     #[tracing::instrument(skip(self))]
-    fn delete_redirect(&self, redirect_ref: &str) -> LibResult<usize> {
-        let mut vec = self.storage.write()?;
-        for i in 0..vec.len() {
-            if vec[i].public_ref == redirect_ref || vec[i].alias == redirect_ref {
-                vec.remove(i);
+    fn delete_redirect(&self, namespace: &str, redirect_ref: &str) -> LibResult<usize> {
+        let mut namespaces = self.namespaces.write()?;
+        if let Some(store) = namespaces.get_mut(namespace) {
+            if let Some(i) = self.position(&store.redirects, redirect_ref) {
+                store.redirects.remove(i);
+                store.refresh_patterns();
                 return Ok(1);
             }
         }
+
         Err(GadgetLibError::RedirectDoesNotExists(
             redirect_ref.to_string(),
         ))
     }
 
     #[tracing::instrument(skip(self))]
-    fn get_all(&self, page: u64, limit: usize) -> LibResult<Vec<RedirectModel>> {
+    fn record_hit(&self, namespace: &str, redirect_ref: &str) -> LibResult<()> {
+        let mut namespaces = self.namespaces.write()?;
+        if let Some(store) = namespaces.get_mut(namespace) {
+            if let Some(i) = self.position(&store.redirects, redirect_ref) {
+                store.redirects[i].hits += 1;
+            }
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn use_redirect(
+        &self,
+        namespace: &str,
+        redirect_ref: &str,
+        now: NaiveDateTime,
+    ) -> LibResult<bool> {
+        let mut namespaces = self.namespaces.write()?;
+        let redirect = namespaces.get_mut(namespace).and_then(|store| {
+            let i = self.position(&store.redirects, redirect_ref)?;
+            Some(&mut store.redirects[i])
+        });
+        match redirect {
+            Some(redirect) if redirect.is_expired(now) => Ok(false),
+            Some(redirect) => {
+                redirect.hits += 1;
+                Ok(true)
+            }
+            None => Err(GadgetLibError::RedirectDoesNotExists(
+                redirect_ref.to_string(),
+            )),
+        }
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn get_all(&self, namespace: &str, page: u64, limit: usize) -> LibResult<Vec<RedirectModel>> {
+        let begin: usize = limit * page as usize;
+        let namespaces = self.namespaces.read()?;
+        Ok(namespaces
+            .get(namespace)
+            .map(|store| {
+                store
+                    .redirects
+                    .iter()
+                    .skip(begin)
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn sweep_expired(&self, namespace: &str, now: NaiveDateTime) -> LibResult<Vec<RedirectModel>> {
+        let mut namespaces = self.namespaces.write()?;
+        let store = match namespaces.get_mut(namespace) {
+            Some(store) => store,
+            None => return Ok(Vec::new()),
+        };
+        let (expired, live): (Vec<RedirectModel>, Vec<RedirectModel>) = store
+            .redirects
+            .drain(..)
+            .partition(|redirect| redirect.is_expired(now));
+        store.redirects = live;
+        if expired.is_empty() {
+            return Ok(expired);
+        }
+
+        store.refresh_patterns();
+        store
+            .history
+            .extend(expired.iter().map(|redirect| HistoryEntry {
+                recorded_on: now,
+                event: HistoryEvent::Expired,
+                redirect: redirect.clone(),
+            }));
+        Ok(expired)
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn record_scheduled_changes(
+        &self,
+        namespace: &str,
+        since: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> LibResult<Vec<HistoryEntry>> {
+        let mut namespaces = self.namespaces.write()?;
+        let store = match namespaces.get_mut(namespace) {
+            Some(store) => store,
+            None => return Ok(Vec::new()),
+        };
+        let mut switches = Vec::new();
+        for redirect in store.redirects.iter() {
+            let schedule = &redirect.options.schedule;
+            let (started, change) = match latest_change(schedule, now) {
+                Some(latest) => latest,
+                None => continue,
+            };
+            let before = active_destination(&redirect.destination, schedule, since);
+            if started > since && before != change.destination {
+                switches.push(HistoryEntry {
+                    recorded_on: started,
+                    event: HistoryEvent::Switched {
+                        from: before.to_owned(),
+                        to: change.destination.clone(),
+                    },
+                    redirect: redirect.clone(),
+                });
+            }
+        }
+
+        store.history.extend(switches.iter().cloned());
+        Ok(switches)
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn get_history(
+        &self,
+        namespace: &str,
+        page: u64,
+        limit: usize,
+    ) -> LibResult<Vec<HistoryEntry>> {
         let begin: usize = limit * page as usize;
-        let end: usize = begin + limit;
-        let v = self.storage.read()?;
-        let end = std::cmp::min(end, v.len());
-        let data = v.get((begin)..(end)).unwrap_or_default().to_vec();
-        Ok(data)
+        let namespaces = self.namespaces.read()?;
+        Ok(namespaces
+            .get(namespace)
+            .map(|store| {
+                store
+                    .history
+                    .iter()
+                    .skip(begin)
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
     }
+
+    fn namespaces(&self) -> LibResult<Vec<String>> {
+        Ok(self.namespaces.read()?.keys().cloned().collect())
+    }
+
+    fn get_tokens(&self) -> LibResult<Vec<ApiTokenModel>> {
+        self.get_internal_tokens()
+    }
+
+    fn create_token(&self, token: &ApiTokenModel) -> LibResult<()> {
+        self.tokens.write()?.push(token.clone());
+        Ok(())
+    }
+
+    fn delete_token(&self, token_id: &str) -> LibResult<usize> {
+        let mut tokens = self.tokens.write()?;
+        let before = tokens.len();
+        tokens.retain(|token| token.token_id != token_id);
+        Ok(before - tokens.len())
+    }
+}
+
+#[test]
+fn update_keeps_the_owner() {
+    use crate::prelude::Backend;
+
+    let backend = InMemoryBackend::new(Default::default());
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "docs",
+            "https://docs.example.com",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    let updated = backend
+        .update_redirect(
+            DEFAULT_NAMESPACE,
+            "docs",
+            "https://wiki.example.com",
+            None,
+            "alice",
+        )
+        .unwrap();
+
+    assert_eq!(Some("bob".to_owned()), updated.created_by);
+    assert_eq!(Some("alice".to_owned()), updated.last_edited_by);
+}
+
+#[test]
+fn sweeps_expired_redirects_into_history() {
+    use crate::prelude::Backend;
+    use chrono::Duration;
+
+    let now = chrono::Utc::now().naive_utc();
+    let backend = InMemoryBackend::new(Default::default());
+    let expiring = RedirectOptions {
+        expires_at: Some(now - Duration::minutes(1)),
+        ..Default::default()
+    };
+    let one_time = RedirectOptions {
+        max_uses: Some(1),
+        ..Default::default()
+    };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "old",
+            "https://example.com/old",
+            &expiring,
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "once",
+            "https://example.com/once",
+            &one_time,
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "docs",
+            "https://docs.example.com",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    let swept: Vec<String> = backend
+        .sweep_expired(DEFAULT_NAMESPACE, now)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.alias)
+        .collect();
+    assert_eq!(vec!["old".to_owned()], swept);
+
+    assert!(backend
+        .use_redirect(DEFAULT_NAMESPACE, "once", now)
+        .unwrap());
+    assert!(!backend
+        .use_redirect(DEFAULT_NAMESPACE, "once", now)
+        .unwrap());
+    backend.sweep_expired(DEFAULT_NAMESPACE, now).unwrap();
+    assert_eq!(
+        None,
+        backend.get_redirect(DEFAULT_NAMESPACE, "once").unwrap()
+    );
+    assert!(backend
+        .get_redirect(DEFAULT_NAMESPACE, "docs")
+        .unwrap()
+        .is_some());
+
+    let history = backend.get_history(DEFAULT_NAMESPACE, 0, 10).unwrap();
+    assert_eq!(2, history.len());
+    assert_eq!(HistoryEvent::Expired, history[1].event);
+    assert_eq!("once", history[1].redirect.alias);
+}
+
+#[test]
+fn records_scheduled_changes() {
+    use crate::prelude::Backend;
+    use crate::schedule::{upcoming_changes, ScheduledChange};
+    use chrono::Duration;
+
+    let now = chrono::Utc::now().naive_utc();
+    let backend = InMemoryBackend::new(Default::default());
+    let options = RedirectOptions {
+        schedule: vec![
+            ScheduledChange {
+                destination: "https://example.com/sprint-1".to_owned(),
+                starts_at: now - Duration::minutes(5),
+                every: None,
+            },
+            ScheduledChange {
+                destination: "https://example.com/sprint-2".to_owned(),
+                starts_at: now + Duration::days(14),
+                every: None,
+            },
+        ],
+        ..Default::default()
+    };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "sprint",
+            "https://example.com/board",
+            &options,
+            "bob",
+        )
+        .unwrap();
+
+    let switches = backend
+        .record_scheduled_changes(DEFAULT_NAMESPACE, now - Duration::minutes(10), now)
+        .unwrap();
+    assert_eq!(1, switches.len());
+    assert_eq!(
+        HistoryEvent::Switched {
+            from: "https://example.com/board".to_owned(),
+            to: "https://example.com/sprint-1".to_owned()
+        },
+        switches[0].event
+    );
+    assert!(backend
+        .record_scheduled_changes(DEFAULT_NAMESPACE, now, now + Duration::minutes(10))
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        1,
+        backend.get_history(DEFAULT_NAMESPACE, 0, 10).unwrap().len()
+    );
+
+    let upcoming = upcoming_changes(&backend.get_all(DEFAULT_NAMESPACE, 0, 10).unwrap(), now);
+    assert_eq!(1, upcoming.len());
+    assert_eq!("https://example.com/sprint-2", upcoming[0].destination);
+}
+
+#[test]
+fn keeps_namespaces_apart() {
+    use crate::prelude::Backend;
+
+    let backend = InMemoryBackend::new(Default::default());
+    for (namespace, destination) in [
+        (DEFAULT_NAMESPACE, "https://oncall.example.com"),
+        ("sre", "https://pager.example.com/sre"),
+    ] {
+        backend
+            .create_redirect(namespace, "oncall", destination, &Default::default(), "bob")
+            .unwrap();
+    }
+
+    assert_eq!(
+        "https://pager.example.com/sre",
+        backend
+            .get_redirect("sre", "oncall")
+            .unwrap()
+            .unwrap()
+            .destination
+    );
+    assert_eq!(None, backend.get_redirect("web", "oncall").unwrap());
+    backend.delete_redirect("sre", "oncall").unwrap();
+    assert!(backend
+        .get_redirect(DEFAULT_NAMESPACE, "oncall")
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        vec![DEFAULT_NAMESPACE.to_owned(), "sre".to_owned()],
+        backend.namespaces().unwrap()
+    );
 }
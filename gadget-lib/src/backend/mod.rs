@@ -1,27 +1,93 @@
 mod json;
 mod memory;
+use crate::normalize::AliasNormalization;
+use crate::passthrough::QueryPassthrough;
+use crate::pattern::{AliasPattern, PatternMatch};
 use crate::prelude::LibResult;
+use crate::schedule::ScheduledChange;
+use crate::token::ApiTokenModel;
+use crate::visibility::Visibility;
+use crate::weighted::{Selection, WeightedDestination};
 
-pub trait Backend<'a> {
-    fn get_redirect(&self, redirect_ref: &str) -> LibResult<Option<RedirectModel>>;
+/// Every redirect is in a namespace, which has its own aliases and history. A namespace
+/// doesn't need to be created, it's empty until its first redirect.
+///
+/// Backends are shared by every request the server handles, so they are `Send + Sync`.
+pub trait Backend<'a>: Send + Sync {
+    fn get_redirect(&self, namespace: &str, redirect_ref: &str)
+        -> LibResult<Option<RedirectModel>>;
+
+    /// Find the highest precedence pattern redirect that matches `alias`. Patterns see the alias
+    /// as it was given, not normalized.
+    fn find_pattern(&self, namespace: &str, alias: &str) -> LibResult<Option<PatternMatch>>;
+
+    /// How the backend compares aliases.
+    fn normalization(&self) -> AliasNormalization;
+
+    /// Aliases that are stored separately, but are the same alias under the backend's normalization.
+    fn alias_collisions(&self, namespace: &str) -> LibResult<Vec<Vec<String>>>;
 
     fn create_redirect(
         &self,
+        namespace: &str,
         new_alias: &str,
         new_destination: &str,
+        options: &RedirectOptions,
         username: &str,
     ) -> LibResult<RedirectModel>;
 
+    /// Update where a redirect goes. When `options` is `None` the existing options are kept.
     fn update_redirect(
         &self,
+        namespace: &str,
         redirect_ref: &str,
         new_dest: &str,
+        options: Option<&RedirectOptions>,
         username: &str,
     ) -> LibResult<RedirectModel>;
 
-    fn delete_redirect(&self, redirect_ref: &str) -> LibResult<usize>;
+    fn delete_redirect(&self, namespace: &str, redirect_ref: &str) -> LibResult<usize>;
+
+    /// Count a use of the redirect. Backends may hold on to the count until their next write.
+    fn record_hit(&self, namespace: &str, redirect_ref: &str) -> LibResult<()>;
+
+    /// Count a use of the redirect, unless it has expired by `now`, and return whether it was
+    /// used. The check and the count happen together, so a limited-use redirect can't be used
+    /// more often than it allows by requests that arrive at the same time.
+    fn use_redirect(
+        &self,
+        namespace: &str,
+        redirect_ref: &str,
+        now: NaiveDateTime,
+    ) -> LibResult<bool>;
+
+    fn get_all(&self, namespace: &str, page: u64, limit: usize) -> LibResult<Vec<RedirectModel>>;
+
+    /// Move every redirect that has expired by `now` into the history, and return them.
+    fn sweep_expired(&self, namespace: &str, now: NaiveDateTime) -> LibResult<Vec<RedirectModel>>;
+
+    /// Record, in the history, every scheduled change that switched a redirect's destination
+    /// after `since` and up to `now`.
+    fn record_scheduled_changes(
+        &self,
+        namespace: &str,
+        since: NaiveDateTime,
+        now: NaiveDateTime,
+    ) -> LibResult<Vec<HistoryEntry>>;
+
+    /// Oldest entries first.
+    fn get_history(&self, namespace: &str, page: u64, limit: usize)
+        -> LibResult<Vec<HistoryEntry>>;
+
+    /// Every namespace that has redirects or history.
+    fn namespaces(&self) -> LibResult<Vec<String>>;
 
-    fn get_all(&self, page: u64, limit: usize) -> LibResult<Vec<RedirectModel>>;
+    /// API tokens are kept for the whole backend, not per namespace.
+    fn get_tokens(&self) -> LibResult<Vec<ApiTokenModel>>;
+
+    fn create_token(&self, token: &ApiTokenModel) -> LibResult<()>;
+
+    fn delete_token(&self, token_id: &str) -> LibResult<usize>;
 }
 
 use chrono::{NaiveDateTime, Utc};
@@ -35,6 +101,111 @@ pub struct RedirectModel {
     pub destination: String,
     pub created_on: NaiveDateTime,
     pub created_by: Option<String>,
+    /// Who changed the redirect last, `None` when it hasn't been changed since it was created.
+    #[serde(default)]
+    pub last_edited_by: Option<String>,
+    #[serde(default)]
+    pub options: RedirectOptions,
+    /// How many times the redirect has been used.
+    #[serde(default)]
+    pub hits: u64,
+}
+
+/// Per-redirect settings, mostly about how a redirect is evaluated.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(default)]
+pub struct RedirectOptions {
+    /// When the redirect is matched by a prefix of the alias (`docs` for `docs/runbooks/db`),
+    /// append the rest of the path (`/runbooks/db`) to the destination.
+    pub path_passthrough: bool,
+    /// How the alias is compared against incoming aliases.
+    pub pattern: AliasPattern,
+    /// Patterns are tried lowest priority first. Exact aliases always win over patterns.
+    pub priority: i32,
+    /// What the redirect is for, shown when previewing it.
+    pub description: Option<String>,
+    /// The status code the redirect is sent with.
+    pub status: RedirectStatus,
+    /// How many seconds clients may cache the redirect for.
+    pub max_age: Option<u32>,
+    /// What happens to the query string of the incoming link.
+    pub query_passthrough: QueryPassthrough,
+    /// When, in UTC, the redirect stops working.
+    pub expires_at: Option<NaiveDateTime>,
+    /// How many times the redirect can be used before it stops working.
+    pub max_uses: Option<u64>,
+    /// Destinations that take over from the redirect's own destination at set times.
+    pub schedule: Vec<ScheduledChange>,
+    /// Destinations to split the traffic between, instead of the redirect's own destination.
+    pub destinations: Vec<WeightedDestination>,
+    /// How a request is given one of the `destinations`.
+    pub selection: Selection,
+    /// Who can find, list and use the redirect.
+    pub visibility: Visibility,
+}
+
+/// The HTTP status a redirect is sent with, written as its number.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum RedirectStatus {
+    MovedPermanently,
+    Found,
+    #[default]
+    TemporaryRedirect,
+    PermanentRedirect,
+}
+
+impl RedirectStatus {
+    pub fn code(&self) -> u16 {
+        match self {
+            RedirectStatus::MovedPermanently => 301,
+            RedirectStatus::Found => 302,
+            RedirectStatus::TemporaryRedirect => 307,
+            RedirectStatus::PermanentRedirect => 308,
+        }
+    }
+
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            RedirectStatus::MovedPermanently | RedirectStatus::PermanentRedirect
+        )
+    }
+
+    /// The `Cache-Control` header to send with the redirect, if any.
+    ///
+    /// An explicit `max_age` always wins. Otherwise temporary redirects must not be cached,
+    /// and permanent ones are left to the browser.
+    pub fn cache_control(&self, max_age: Option<u32>) -> Option<String> {
+        match max_age {
+            Some(max_age) => Some(format!("max-age={}", max_age)),
+            None if self.is_permanent() => None,
+            None => Some("no-store".to_owned()),
+        }
+    }
+}
+
+impl TryFrom<u16> for RedirectStatus {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        match code {
+            301 => Ok(RedirectStatus::MovedPermanently),
+            302 => Ok(RedirectStatus::Found),
+            307 => Ok(RedirectStatus::TemporaryRedirect),
+            308 => Ok(RedirectStatus::PermanentRedirect),
+            _ => Err(format!(
+                "{} isn't a redirect status, expected 301, 302, 307 or 308",
+                code
+            )),
+        }
+    }
+}
+
+impl From<RedirectStatus> for u16 {
+    fn from(status: RedirectStatus) -> Self {
+        status.code()
+    }
 }
 
 impl RedirectModel {
@@ -42,11 +213,27 @@ impl RedirectModel {
         self.destination = destination.to_string();
     }
 
-    pub fn update_username(&mut self, username: Option<&str>) {
-        self.created_by = username.map(|x| x.to_string());
+    pub fn set_options(&mut self, options: &RedirectOptions) {
+        self.options = options.clone();
     }
 
-    pub fn new(id: i32, alias: &str, destination: &str, created_by: Option<String>) -> Self {
+    pub fn set_last_edited_by(&mut self, username: Option<&str>) {
+        self.last_edited_by = username.map(|x| x.to_string());
+    }
+
+    /// Past its `expires_at`, or already used `max_uses` times.
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        matches!(self.options.expires_at, Some(expires_at) if expires_at <= now)
+            || matches!(self.options.max_uses, Some(max_uses) if self.hits >= max_uses)
+    }
+
+    pub fn new(
+        id: i32,
+        alias: &str,
+        destination: &str,
+        options: &RedirectOptions,
+        created_by: Option<String>,
+    ) -> Self {
         RedirectModel {
             redirect_id: id,
             public_ref: make_random_id(),
@@ -54,14 +241,46 @@ impl RedirectModel {
             destination: destination.to_string(),
             created_on: Utc::now().naive_utc(),
             created_by,
+            last_edited_by: None,
+            options: options.clone(),
+            hits: 0,
         }
     }
 }
 
+/// Something that happened to a redirect, kept after the redirect changed or went away.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct HistoryEntry {
+    pub recorded_on: NaiveDateTime,
+    pub event: HistoryEvent,
+    /// The redirect as it was when it happened.
+    pub redirect: RedirectModel,
+}
+
+/// The redirects and history of one namespace, as they are stored.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct NamespaceData {
+    pub redirects: Vec<RedirectModel>,
+    #[serde(default)]
+    pub history: Vec<HistoryEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryEvent {
+    /// The redirect expired and was swept out of the live redirects.
+    Expired,
+    /// A scheduled change took over, `from` is the destination before it.
+    Switched { from: String, to: String },
+}
+
 pub mod prelude {
     pub use super::json::JsonBackend;
     pub use super::memory::InMemoryBackend;
-    pub use super::{Backend, RedirectModel};
+    pub use super::{
+        Backend, HistoryEntry, HistoryEvent, NamespaceData, RedirectModel, RedirectOptions,
+        RedirectStatus,
+    };
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -92,3 +311,21 @@ fn make_random_id() -> String {
 
     String::from_utf8(bytes).expect("Found invalid UTF-8")
 }
+
+#[test]
+fn redirect_status_sets_cache_control() {
+    let status: RedirectStatus = serde_json::from_str("308").unwrap();
+    assert_eq!(RedirectStatus::PermanentRedirect, status);
+    assert_eq!("308", serde_json::to_string(&status).unwrap());
+    assert!(serde_json::from_str::<RedirectStatus>("200").is_err());
+
+    assert_eq!(None, status.cache_control(None));
+    assert_eq!(
+        Some("max-age=60".to_owned()),
+        status.cache_control(Some(60))
+    );
+    assert_eq!(
+        Some("no-store".to_owned()),
+        RedirectStatus::default().cache_control(None)
+    );
+}
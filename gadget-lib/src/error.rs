@@ -10,8 +10,30 @@ pub enum GadgetLibError {
     RedirectExists(String),
     #[error("Redirect {0} does not exists")]
     RedirectDoesNotExists(String),
+    #[error("Redirect {0} has expired")]
+    RedirectExpired(String),
     #[error("Unknown backend for {0}")]
     UnknownBackend(String),
+    #[error("Alias chain loops back on itself: {0}")]
+    AliasLoop(String),
+    #[error("Alias chain for `{0}` is too deep")]
+    AliasDepthExceeded(String),
+    #[error("Invalid alias pattern {0}")]
+    InvalidPattern(String),
+    #[error("Invalid schedule: {0}")]
+    InvalidSchedule(String),
+    #[error("Invalid miss policy {0}, expected 404, suggest, search:<url> or upstream:<url>")]
+    InvalidMissPolicy(String),
+    #[error("Invalid short code settings: {0}")]
+    InvalidShortCode(String),
+    #[error("Unable to find an unused short code after {0} attempts")]
+    ShortCodesExhausted(usize),
+    #[error("Only its owner can change the personal alias {0}")]
+    PersonalAliasDenied(String),
+    #[error("Invalid namespace settings: {0}")]
+    InvalidNamespace(String),
+    #[error("Only the admins of namespace {0} can change its redirects")]
+    NamespaceAdminOnly(String),
 }
 
 impl<T> From<std::sync::PoisonError<T>> for GadgetLibError {
@@ -1,8 +1,23 @@
 mod backend;
 mod error;
+pub mod miss;
+pub mod namespace;
+pub mod negotiate;
+pub mod normalize;
+pub mod passthrough;
+pub mod pattern;
+pub mod personal;
+pub mod resolve;
+pub mod schedule;
+pub mod shortcode;
+pub mod suggest;
+pub mod token;
+pub mod visibility;
+pub mod weighted;
 
 use crate::backend::prelude::*;
 use api::ApiRedirect;
+use normalize::AliasNormalization;
 use prelude::{GadgetLibError, LibResult};
 use std::path::PathBuf;
 use tracing::{debug, warn};
@@ -11,6 +26,8 @@ pub mod prelude {
     pub use crate::backend::prelude::*;
     pub use crate::create_backend;
     pub use crate::error::GadgetLibError;
+    pub use crate::namespace::DEFAULT_NAMESPACE;
+    pub use crate::resolve::resolve_destination;
     pub use crate::{AliasRedirect, Redirect};
 
     pub type LibResult<T> = std::result::Result<T, GadgetLibError>;
@@ -18,6 +35,11 @@ pub mod prelude {
 
 pub mod api {
 
+    use crate::prelude::{HistoryEntry, RedirectOptions, RedirectStatus};
+    use crate::schedule::UpcomingChange;
+    use crate::suggest::Suggestion;
+    use crate::token::{ApiTokenModel, TokenScope};
+    use chrono::NaiveDateTime;
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize)]
@@ -27,20 +49,155 @@ pub mod api {
 
     #[derive(Deserialize, Serialize, Debug)]
     pub struct ApiRedirect {
+        /// When creating a redirect without an alias, a short code is generated for it.
+        #[serde(default)]
         pub alias: String,
         pub destination: String,
         pub created_by: Option<UserDetails>,
+        #[serde(default)]
+        pub last_edited_by: Option<UserDetails>,
+        #[serde(default)]
+        pub options: RedirectOptions,
+        /// How many times the redirect has been used, ignored when creating a redirect.
+        #[serde(default)]
+        pub hits: u64,
+        /// The full link to the redirect, when the server knows its host.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub short_url: Option<String>,
     }
 
     #[derive(Deserialize, Serialize, Debug)]
     pub struct UpdateRedirect {
         pub destination: String,
         pub created_by: Option<UserDetails>,
+        /// When missing, the redirect keeps its current options.
+        #[serde(default)]
+        pub options: Option<RedirectOptions>,
     }
 
     #[derive(Deserialize, Serialize, Debug)]
+    pub struct HistoryList {
+        pub history: Vec<HistoryEntry>,
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct UpcomingChangeList {
+        pub changes: Vec<UpcomingChange>,
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct SuggestionList {
+        pub query: String,
+        pub suggestions: Vec<Suggestion>,
+    }
+
+    /// What a query resolves to, as returned by the resolve API.
+    #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+    pub struct ResolvedRedirect {
+        pub query: String,
+        /// The alias that matched the query.
+        pub alias: String,
+        pub destination: String,
+        /// The template arm that gave the destination.
+        #[serde(default)]
+        pub arm: String,
+        #[serde(default)]
+        pub warnings: Vec<String>,
+        #[serde(default)]
+        pub status: RedirectStatus,
+        #[serde(default)]
+        pub max_age: Option<u32>,
+        /// Which of the redirect's weighted destinations was picked.
+        #[serde(default)]
+        pub chosen: Option<String>,
+    }
+
+    impl ResolvedRedirect {
+        pub fn new(query: &str, resolution: crate::resolve::Resolution) -> Self {
+            ResolvedRedirect {
+                query: query.to_owned(),
+                status: resolution.redirect.options.status,
+                max_age: resolution.redirect.options.max_age,
+                alias: resolution.redirect.alias,
+                destination: resolution.destination,
+                arm: resolution.arm,
+                warnings: resolution.warnings,
+                chosen: resolution.chosen,
+            }
+        }
+
+        pub fn cache_control(&self) -> Option<String> {
+            self.status.cache_control(self.max_age)
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct ResolveBatch {
+        pub queries: Vec<String>,
+    }
+
+    /// The answer for one query of a [`ResolveBatch`], `resolved` is missing when nothing matched.
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct ResolveBatchItem {
+        pub query: String,
+        pub resolved: Option<ResolvedRedirect>,
+        pub error: Option<String>,
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct ResolveBatchResult {
+        pub results: Vec<ResolveBatchItem>,
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct NewApiToken {
+        pub scope: TokenScope,
+        /// When, in UTC, the token stops working.
+        pub expires_at: NaiveDateTime,
+        #[serde(default)]
+        pub description: Option<String>,
+    }
+
+    /// An API token, without its hash.
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct ApiToken {
+        pub token_id: String,
+        pub username: String,
+        pub scope: TokenScope,
+        #[serde(default)]
+        pub description: Option<String>,
+        pub created_on: NaiveDateTime,
+        pub expires_at: NaiveDateTime,
+        /// The token itself, only when it was just created.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub token: Option<String>,
+    }
+
+    impl From<ApiTokenModel> for ApiToken {
+        fn from(model: ApiTokenModel) -> Self {
+            ApiToken {
+                token_id: model.token_id,
+                username: model.username,
+                scope: model.scope,
+                description: model.description,
+                created_on: model.created_on,
+                expires_at: model.expires_at,
+                token: None,
+            }
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct ApiTokenList {
+        pub tokens: Vec<ApiToken>,
+    }
+
+    #[derive(Deserialize, Serialize, Debug, Clone, Default)]
     pub struct UserDetails {
         pub username: String,
+        /// Groups the user is in, which restricted redirects can be shared with.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub groups: Vec<String>,
     }
 
     impl From<crate::prelude::RedirectModel> for ApiRedirect {
@@ -50,19 +207,32 @@ pub mod api {
                 destination: model.destination,
                 created_by: model.created_by.map(|name| UserDetails {
                     username: name,
+                    groups: Vec::new(),
+                }),
+                last_edited_by: model.last_edited_by.map(|username| UserDetails {
+                    username,
+                    groups: Vec::new(),
                 }),
+                options: model.options,
+                hits: model.hits,
+                short_url: None,
             }
         }
     }
 }
 
-pub fn create_backend<'a>(url: String) -> LibResult<Box<dyn Backend<'a>>> {
+pub fn create_backend<'a>(
+    url: String,
+    normalization: AliasNormalization,
+) -> LibResult<Box<dyn Backend<'a>>> {
     if url.starts_with("file://") {
         let path = PathBuf::from(url);
-        let json_backend = JsonBackend::new(path)?;
+        let json_backend = JsonBackend::new(path)?.with_normalization(normalization);
         Ok(Box::new(json_backend))
     } else if url.starts_with("memory://") {
-        Ok(Box::new(InMemoryBackend::new(Default::default())))
+        Ok(Box::new(
+            InMemoryBackend::new(Default::default()).with_normalization(normalization),
+        ))
     } else {
         Err(GadgetLibError::UnknownBackend(url))
     }
@@ -71,7 +241,9 @@ pub fn create_backend<'a>(url: String) -> LibResult<Box<dyn Backend<'a>>> {
 pub trait Redirect {
     fn get_destination(&self, input: &str) -> String;
     fn evaluate(&self, input: &str) -> String;
-    fn matches(&self, alias: &str) -> bool;
+    /// Does this redirect handle `alias`, either exactly or as a `/` separated prefix of it, when
+    /// aliases are compared under `normalization`.
+    fn matches(&self, alias: &str, normalization: &AliasNormalization) -> bool;
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -93,6 +265,17 @@ impl DestPart {
 pub struct AliasRedirect {
     alias: String,
     destinations: Vec<DestPart>,
+    warnings: Vec<String>,
+}
+
+/// How a destination was worked out for some input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Evaluation {
+    pub destination: String,
+    /// The template arm that was used, before the arguments were put in.
+    pub arm: String,
+    /// Things about the destination, or how it was used, that probably aren't what was intended.
+    pub warnings: Vec<String>,
 }
 
 impl From<RedirectModel> for AliasRedirect {
@@ -126,6 +309,7 @@ impl AliasRedirect {
         };
 
         let mut destinations = Vec::new();
+        let mut warnings = Vec::new();
 
         let mut parts: Vec<_> = destination.split(|x: char| x == '{' || x == '}').collect();
 
@@ -137,11 +321,19 @@ impl AliasRedirect {
 
         if last_open > first_close {
             warn!("Destination has mismatched params: `{}`", destination);
+            warnings.push(format!(
+                "Destination has mismatched params: `{}`",
+                destination
+            ));
             destinations.push(DestPart::new(number_of_components, destination.to_owned()));
         } else if parts.is_empty() {
             destinations.push(DestPart::new(number_of_components, destination.to_owned()));
         } else if parts.len() % 2 != 0 {
             warn!("Destination has missmatched `{{` | `}}`: `{}`", destination);
+            warnings.push(format!(
+                "Destination has missmatched `{{` | `}}`: `{}`",
+                destination
+            ));
             destinations.push(DestPart::new(number_of_components, destination.to_owned()));
         } else {
             let mut pre = "".to_owned();
@@ -166,27 +358,24 @@ impl AliasRedirect {
         AliasRedirect {
             alias,
             destinations,
+            warnings,
         }
     }
-}
 
-impl Redirect for AliasRedirect {
-    #[tracing::instrument(skip(self))]
-    fn get_destination(&self, input: &str) -> String {
+    /// Like [`Redirect::get_destination`], but also says which arm was used and what looked wrong.
+    pub fn explain_query(&self, input: &str) -> Evaluation {
         let parsed_input = match urlencoding::decode(input) {
             Ok(s) => s.to_string(),
             Err(_) => input.to_owned(),
         };
 
-        debug!("Parsed Input: {}", parsed_input);
-
         let mut inputs: Vec<&str> = parsed_input.split(' ').collect();
         inputs.remove(0);
-        self.evaluate(&inputs.join(" "))
+        self.explain(&inputs.join(" "))
     }
 
-    #[tracing::instrument(skip(self))]
-    fn evaluate(&self, input: &str) -> String {
+    /// Like [`Redirect::evaluate`], but also says which arm was used and what looked wrong.
+    pub fn explain(&self, input: &str) -> Evaluation {
         let parsed_input = match urlencoding::decode(input) {
             Ok(s) => s.to_string(),
             Err(_) => input.to_owned(),
@@ -208,21 +397,50 @@ impl Redirect for AliasRedirect {
             self.destinations.last().unwrap()
         };
 
+        let mut warnings = self.warnings.clone();
         let mut destination = part.dest.clone();
 
         for i in 1..=part.number_of_components {
             destination = destination.replace(&format!("${}", i), inputs.remove(0));
         }
 
+        // `alias:` destinations hand the extra arguments on to the next alias.
+        if !inputs.is_empty() && !part.dest.starts_with(resolve::ALIAS_SCHEME) {
+            warnings.push(format!(
+                "More arguments than the destination uses, `{}` was appended to it",
+                inputs.join(" ")
+            ));
+        }
+
         if !inputs.is_empty() {
             destination = format!("{} {}", destination, inputs.join(" "));
         }
 
-        destination
+        Evaluation {
+            destination,
+            arm: part.dest.clone(),
+            warnings,
+        }
     }
+}
 
-    fn matches(&self, alias: &str) -> bool {
-        self.alias == alias.to_lowercase()
+impl Redirect for AliasRedirect {
+    #[tracing::instrument(skip(self))]
+    fn get_destination(&self, input: &str) -> String {
+        self.explain_query(input).destination
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn evaluate(&self, input: &str) -> String {
+        self.explain(input).destination
+    }
+
+    fn matches(&self, alias: &str, normalization: &AliasNormalization) -> bool {
+        let alias = normalization.normalize(alias);
+        match alias.strip_prefix(&normalization.normalize(&self.alias)) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
     }
 }
 
@@ -245,6 +463,38 @@ fn long_url_with_spaces() {
     );
 }
 
+#[test]
+fn matches_on_path_segments() {
+    let alias = AliasRedirect::new("docs", "https://docs.example.com");
+
+    let normalization = AliasNormalization::default();
+    assert!(alias.matches("/docs", &normalization));
+    assert!(alias.matches("/docs/runbooks/db", &normalization));
+    assert!(!alias.matches("/docsearch", &normalization));
+    assert!(alias.matches("/Docs/runbooks", &normalization));
+    assert!(!alias.matches("/Docs/runbooks", &AliasNormalization::exact()));
+}
+
+#[test]
+fn explains_the_arm_used() {
+    let alias = AliasRedirect::new("google", "https://duckduckgo.com/{?q=$1}");
+
+    let evaluation = alias.explain_query("google");
+    assert_eq!("https://duckduckgo.com/", evaluation.arm);
+    assert!(evaluation.warnings.is_empty());
+
+    let evaluation = alias.explain_query("google rust lang");
+    assert_eq!("https://duckduckgo.com/?q=$1", evaluation.arm);
+    assert_eq!(
+        "https://duckduckgo.com/?q=rust lang",
+        evaluation.destination
+    );
+    assert_eq!(1, evaluation.warnings.len());
+
+    let alias = AliasRedirect::new("broken", "https://example.com/{$1");
+    assert_eq!(1, alias.explain_query("broken").warnings.len());
+}
+
 #[test]
 fn with_just_query() {
     let alias = AliasRedirect::new("google", "https://duckduckgo.com/{?q=$1}");
@@ -0,0 +1,110 @@
+use crate::prelude::{GadgetLibError, LibResult};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Placeholder in a search URL template that is replaced with the query.
+pub const SEARCH_PLACEHOLDER: &str = "%s";
+
+/// What to do when a query doesn't match any redirect.
+///
+/// Written as `404`, `suggest`, `search:<url template>` or `upstream:<url>`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(try_from = "String")]
+pub enum MissPolicy {
+    /// Return a plain 404.
+    NotFound,
+    /// Show the aliases that are close to the query.
+    #[default]
+    Suggest,
+    /// Send the query to a search engine, `%s` in the template is replaced with the query.
+    Search(String),
+    /// Send the query to another gadget instance.
+    Upstream(String),
+}
+
+/// The outcome of a miss, for the server or worker to turn into a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MissAction {
+    NotFound,
+    Suggest,
+    Redirect(String),
+}
+
+impl MissPolicy {
+    pub fn action(&self, query: &str) -> MissAction {
+        match self {
+            MissPolicy::NotFound => MissAction::NotFound,
+            MissPolicy::Suggest => MissAction::Suggest,
+            MissPolicy::Search(template) => MissAction::Redirect(
+                template.replace(SEARCH_PLACEHOLDER, &urlencoding::encode(query)),
+            ),
+            MissPolicy::Upstream(base) => MissAction::Redirect(format!(
+                "{}/{}",
+                base.trim_end_matches('/'),
+                query.replace(' ', "%20")
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for MissPolicy {
+    type Error = GadgetLibError;
+
+    fn try_from(policy: String) -> LibResult<Self> {
+        policy.parse()
+    }
+}
+
+impl FromStr for MissPolicy {
+    type Err = GadgetLibError;
+
+    fn from_str(policy: &str) -> LibResult<Self> {
+        let url = |url: &str| match url::Url::parse(&url.replace(SEARCH_PLACEHOLDER, "")) {
+            Ok(_) => Ok(url.to_owned()),
+            Err(e) => Err(GadgetLibError::InvalidMissPolicy(format!(
+                "{}: {}",
+                policy, e
+            ))),
+        };
+
+        match policy.split_once(':') {
+            Some(("search", template)) => Ok(MissPolicy::Search(url(template)?)),
+            Some(("upstream", base)) => Ok(MissPolicy::Upstream(url(base)?)),
+            _ => match policy {
+                "404" => Ok(MissPolicy::NotFound),
+                "suggest" => Ok(MissPolicy::Suggest),
+                _ => Err(GadgetLibError::InvalidMissPolicy(policy.to_owned())),
+            },
+        }
+    }
+}
+
+#[test]
+fn parses_policies() {
+    assert_eq!(MissPolicy::NotFound, "404".parse().unwrap());
+    assert_eq!(MissPolicy::Suggest, "suggest".parse().unwrap());
+    assert_eq!(
+        MissPolicy::Search("https://duckduckgo.com/?q=%s".to_owned()),
+        "search:https://duckduckgo.com/?q=%s".parse().unwrap()
+    );
+    assert_eq!(
+        MissPolicy::Upstream("https://go.example.com".to_owned()),
+        "upstream:https://go.example.com".parse().unwrap()
+    );
+    assert!("redirect".parse::<MissPolicy>().is_err());
+    assert!("search:not a url".parse::<MissPolicy>().is_err());
+}
+
+#[test]
+fn actions_for_a_query() {
+    assert_eq!(MissAction::NotFound, MissPolicy::NotFound.action("missing"));
+    assert_eq!(MissAction::Suggest, MissPolicy::Suggest.action("missing"));
+    assert_eq!(
+        MissAction::Redirect("https://duckduckgo.com/?q=rust%20lang".to_owned()),
+        MissPolicy::Search("https://duckduckgo.com/?q=%s".to_owned()).action("rust lang")
+    );
+    assert_eq!(
+        MissAction::Redirect("https://go.example.com/missing%20123".to_owned()),
+        MissPolicy::Upstream("https://go.example.com/".to_owned()).action("missing 123")
+    );
+}
@@ -0,0 +1,201 @@
+use crate::api::UserDetails;
+use crate::miss::MissPolicy;
+use crate::prelude::{GadgetLibError, LibResult};
+use serde::Deserialize;
+
+/// The namespace of requests that no other namespace is selected for.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// A team's own set of aliases, so `go/oncall` can go somewhere different for every team.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Namespace {
+    pub name: String,
+    /// Users who can create, change and delete the namespace's redirects. When there are none,
+    /// anyone can.
+    pub admins: Vec<String>,
+    /// What happens when nothing in the namespace matches, instead of the server's miss policy.
+    pub miss_policy: Option<MissPolicy>,
+    /// Requests to these hosts are in the namespace.
+    pub hosts: Vec<String>,
+    /// Links starting with `<prefix>/` are in the namespace, without the prefix.
+    pub prefix: Option<String>,
+}
+
+impl Namespace {
+    pub fn is_admin(&self, user: Option<&UserDetails>) -> bool {
+        self.admins.is_empty() || matches!(user, Some(user) if self.admins.contains(&user.username))
+    }
+
+    fn has_host(&self, host: &str) -> bool {
+        self.hosts.iter().any(|x| is_same_host(x, host))
+    }
+}
+
+/// Whether a request's `Host` header is for the `configured` host. Hosts are compared without
+/// the request's port, and case insensitive.
+pub fn is_same_host(configured: &str, host: &str) -> bool {
+    let host = host.split(':').next().unwrap_or_default();
+    configured.eq_ignore_ascii_case(host)
+}
+
+/// The configured namespaces, and how a request is put in one of them.
+#[derive(Clone, Debug, Default)]
+pub struct Namespaces {
+    namespaces: Vec<Namespace>,
+}
+
+impl Namespaces {
+    pub fn new(namespaces: Vec<Namespace>) -> LibResult<Self> {
+        for (i, namespace) in namespaces.iter().enumerate() {
+            if namespace.name.is_empty() || namespace.name.contains('/') {
+                return Err(GadgetLibError::InvalidNamespace(format!(
+                    "`{}` isn't a valid name",
+                    namespace.name
+                )));
+            }
+            if let Some(prefix) = &namespace.prefix {
+                if prefix.is_empty() || prefix.contains('/') {
+                    return Err(GadgetLibError::InvalidNamespace(format!(
+                        "`{}` isn't a valid prefix for {}",
+                        prefix, namespace.name
+                    )));
+                }
+            }
+
+            for other in &namespaces[..i] {
+                if other.name == namespace.name {
+                    return Err(GadgetLibError::InvalidNamespace(format!(
+                        "{} is configured twice",
+                        namespace.name
+                    )));
+                }
+                if namespace.prefix.is_some() && other.prefix == namespace.prefix {
+                    return Err(GadgetLibError::InvalidNamespace(format!(
+                        "{} and {} have the same prefix",
+                        other.name, namespace.name
+                    )));
+                }
+                if let Some(host) = namespace.hosts.iter().find(|host| other.has_host(host)) {
+                    return Err(GadgetLibError::InvalidNamespace(format!(
+                        "{} and {} are both for {}",
+                        other.name, namespace.name, host
+                    )));
+                }
+            }
+        }
+
+        Ok(Namespaces { namespaces })
+    }
+
+    /// Namespaces from a JSON list, like `[{"name": "sre", "hosts": ["go.sre.example.com"]}]`.
+    pub fn from_json(json: &str) -> LibResult<Self> {
+        match serde_json::from_str(json) {
+            Ok(namespaces) => Namespaces::new(namespaces),
+            Err(e) => Err(GadgetLibError::InvalidNamespace(e.to_string())),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Namespace> {
+        self.namespaces
+            .iter()
+            .find(|namespace| namespace.name == name)
+    }
+
+    /// Every namespace, including the default one when it isn't configured.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.namespaces.iter().map(|x| x.name.as_str()).collect();
+        if !names.contains(&DEFAULT_NAMESPACE) {
+            names.insert(0, DEFAULT_NAMESPACE);
+        }
+        names
+    }
+
+    /// The namespace a link is in, and the link without the namespace's prefix.
+    ///
+    /// A namespace's prefix wins, then the host the request was for, then the user's default
+    /// team, which is their first group. Everything else is in [`DEFAULT_NAMESPACE`].
+    pub fn select(
+        &self,
+        host: Option<&str>,
+        link: &str,
+        user: Option<&UserDetails>,
+    ) -> (String, String) {
+        if let Some((first, rest)) = link.split_once('/') {
+            if let Some(namespace) = self
+                .namespaces
+                .iter()
+                .find(|namespace| namespace.prefix.as_deref() == Some(first))
+            {
+                return (namespace.name.clone(), rest.to_owned());
+            }
+        }
+
+        let by_host = host.and_then(|host| self.namespaces.iter().find(|x| x.has_host(host)));
+        let by_team = user
+            .and_then(|user| user.groups.first())
+            .and_then(|team| self.get(team));
+        let name = match by_host.or(by_team) {
+            Some(namespace) => namespace.name.clone(),
+            None => DEFAULT_NAMESPACE.to_owned(),
+        };
+        (name, link.to_owned())
+    }
+
+    /// Whether `user` can create, change and delete redirects in `namespace`.
+    pub fn is_admin(&self, namespace: &str, user: Option<&UserDetails>) -> bool {
+        match self.get(namespace) {
+            Some(namespace) => namespace.is_admin(user),
+            None => true,
+        }
+    }
+}
+
+#[test]
+fn selects_by_prefix_host_and_team() {
+    let namespaces = Namespaces::from_json(
+        r#"[
+            {"name": "sre", "prefix": "sre", "hosts": ["go.sre.example.com"], "admins": ["alice"]},
+            {"name": "web", "miss_policy": "404"}
+        ]"#,
+    )
+    .unwrap();
+    let user = |groups: &[&str]| UserDetails {
+        username: "bob".to_owned(),
+        groups: groups.iter().map(|x| x.to_string()).collect(),
+    };
+
+    assert_eq!(
+        ("sre".to_owned(), "oncall".to_owned()),
+        namespaces.select(None, "sre/oncall", None)
+    );
+    assert_eq!(
+        ("sre".to_owned(), "oncall".to_owned()),
+        namespaces.select(
+            Some("GO.sre.example.com:8080"),
+            "oncall",
+            Some(&user(&["web"]))
+        )
+    );
+    assert_eq!(
+        ("web".to_owned(), "oncall".to_owned()),
+        namespaces.select(Some("go"), "oncall", Some(&user(&["web", "sre"])))
+    );
+    assert_eq!(
+        (DEFAULT_NAMESPACE.to_owned(), "oncall".to_owned()),
+        namespaces.select(Some("go"), "oncall", Some(&user(&["dev"])))
+    );
+
+    assert!(namespaces.is_admin("web", None));
+    assert!(!namespaces.is_admin("sre", Some(&user(&[]))));
+    assert_eq!(
+        Some(MissPolicy::NotFound),
+        namespaces.get("web").unwrap().miss_policy
+    );
+    assert_eq!(vec![DEFAULT_NAMESPACE, "sre", "web"], namespaces.names());
+
+    assert!(Namespaces::from_json(
+        r#"[{"name": "a", "prefix": "x"}, {"name": "b", "prefix": "x"}]"#
+    )
+    .is_err());
+}
@@ -0,0 +1,70 @@
+/// How a redirect is answered, chosen from the request's `Accept` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// Browsers get a plain redirect.
+    Redirect,
+    /// The same fields as the resolve API.
+    Json,
+    /// A redirect with the destination in the body, so `curl go/alias` prints it.
+    Text,
+}
+
+impl ResponseFormat {
+    /// Picks the format the client prefers most. Clients that don't say, like `curl`
+    /// with its `*/*`, get [`ResponseFormat::Text`]. Media types with `q=0` aren't acceptable.
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        let mut media_types: Vec<(&str, f32)> = accept
+            .unwrap_or_default()
+            .split(',')
+            .map(|media_type| {
+                let mut params = media_type.split(';');
+                let name = params.next().unwrap_or_default().trim();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|quality| quality.parse().ok())
+                    .unwrap_or(1.0);
+                (name, quality)
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        media_types.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        for (name, _) in media_types {
+            match name.to_ascii_lowercase().as_str() {
+                "text/html" | "application/xhtml+xml" => return ResponseFormat::Redirect,
+                "application/json" => return ResponseFormat::Json,
+                "text/plain" => return ResponseFormat::Text,
+                _ => {}
+            }
+        }
+
+        ResponseFormat::Text
+    }
+}
+
+#[test]
+fn picks_format_from_accept() {
+    assert_eq!(
+        ResponseFormat::Redirect,
+        ResponseFormat::from_accept(Some(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        ))
+    );
+    assert_eq!(
+        ResponseFormat::Json,
+        ResponseFormat::from_accept(Some("application/json"))
+    );
+    assert_eq!(
+        ResponseFormat::Json,
+        ResponseFormat::from_accept(Some("text/html;q=0.5, application/json"))
+    );
+    assert_eq!(
+        ResponseFormat::Text,
+        ResponseFormat::from_accept(Some("*/*"))
+    );
+    assert_eq!(
+        ResponseFormat::Redirect,
+        ResponseFormat::from_accept(Some("application/json;q=0, text/html;q=0.1"))
+    );
+    assert_eq!(ResponseFormat::Text, ResponseFormat::from_accept(None));
+}
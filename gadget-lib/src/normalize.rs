@@ -0,0 +1,185 @@
+use crate::prelude::RedirectModel;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use unicode_normalization::UnicodeNormalization;
+
+/// Separators that are considered the same character when `unify_separators` is set.
+const SEPARATORS: [char; 3] = ['-', '_', '.'];
+
+/// How aliases are compared to each other.
+///
+/// Two aliases are the same redirect when their normalized forms are equal. This is used
+/// when looking up a redirect, and when checking that a new alias doesn't already exist.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AliasNormalization {
+    /// `Foo` and `foo` are the same alias.
+    pub case_insensitive: bool,
+    /// `my-alias`, `my_alias` and `my.alias` are the same alias.
+    pub unify_separators: bool,
+    /// Compare aliases after Unicode NFC normalization.
+    pub unicode_nfc: bool,
+}
+
+impl Default for AliasNormalization {
+    fn default() -> Self {
+        AliasNormalization {
+            case_insensitive: true,
+            unify_separators: true,
+            unicode_nfc: true,
+        }
+    }
+}
+
+impl AliasNormalization {
+    /// Exact comparison, aliases are only the same if they are byte for byte equal.
+    pub fn exact() -> Self {
+        AliasNormalization {
+            case_insensitive: false,
+            unify_separators: false,
+            unicode_nfc: false,
+        }
+    }
+
+    pub fn normalize(&self, alias: &str) -> String {
+        let mut alias = if self.unicode_nfc {
+            alias.nfc().collect()
+        } else {
+            alias.to_owned()
+        };
+
+        if self.case_insensitive {
+            alias = alias.to_lowercase();
+        }
+
+        if self.unify_separators {
+            alias = alias.replace(SEPARATORS, "-");
+        }
+
+        alias
+    }
+
+    pub fn is_same(&self, left: &str, right: &str) -> bool {
+        left == right || self.normalize(left) == self.normalize(right)
+    }
+
+    /// Groups of existing aliases that would be the same alias under this normalization.
+    pub fn find_collisions(&self, redirects: &[RedirectModel]) -> Vec<Vec<String>> {
+        let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for redirect in redirects {
+            groups
+                .entry(self.normalize(&redirect.alias))
+                .or_default()
+                .push(redirect.alias.clone());
+        }
+
+        groups
+            .into_values()
+            .filter(|aliases| aliases.len() > 1)
+            .collect()
+    }
+}
+
+#[test]
+fn normalizes_case_separators_and_unicode() {
+    let normalization = AliasNormalization::default();
+
+    assert!(normalization.is_same("Foo", "foo"));
+    assert!(normalization.is_same("my-alias", "MY_ALIAS"));
+    assert!(normalization.is_same("my.alias", "my_alias"));
+    assert!(normalization.is_same("caf\u{e9}", "cafe\u{301}"));
+    assert!(!normalization.is_same("foo", "foo2"));
+
+    let exact = AliasNormalization::exact();
+    assert!(!exact.is_same("Foo", "foo"));
+    assert!(!exact.is_same("my-alias", "my_alias"));
+}
+
+#[test]
+fn reports_collisions() {
+    let options = Default::default();
+    let redirects = vec![
+        RedirectModel::new(1, "Foo", "https://example.com", &options, None),
+        RedirectModel::new(2, "foo", "https://example.com", &options, None),
+        RedirectModel::new(3, "bar", "https://example.com", &options, None),
+    ];
+
+    assert_eq!(
+        vec![vec!["Foo".to_owned(), "foo".to_owned()]],
+        AliasNormalization::default().find_collisions(&redirects)
+    );
+    assert!(AliasNormalization::exact()
+        .find_collisions(&redirects)
+        .is_empty());
+}
+
+#[test]
+fn backend_applies_normalization() {
+    use crate::prelude::*;
+
+    let backend = InMemoryBackend::new(Default::default());
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "My_Link",
+            "https://example.com",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    assert!(matches!(
+        backend.create_redirect(
+            DEFAULT_NAMESPACE,
+            "my-link",
+            "https://example.com",
+            &Default::default(),
+            "bob"
+        ),
+        Err(GadgetLibError::RedirectExists(_))
+    ));
+    assert_eq!(
+        "My_Link",
+        backend
+            .get_redirect(DEFAULT_NAMESPACE, "MY.LINK")
+            .unwrap()
+            .unwrap()
+            .alias
+    );
+
+    let backend =
+        InMemoryBackend::new(Default::default()).with_normalization(AliasNormalization::exact());
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "Foo",
+            "https://example.com",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "foo",
+            "https://example.com",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    assert_eq!(
+        "foo",
+        backend
+            .get_redirect(DEFAULT_NAMESPACE, "foo")
+            .unwrap()
+            .unwrap()
+            .alias
+    );
+    assert!(backend
+        .get_redirect(DEFAULT_NAMESPACE, "FOO")
+        .unwrap()
+        .is_none());
+    assert!(backend
+        .alias_collisions(DEFAULT_NAMESPACE)
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// What happens to the query string of the incoming link.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryPassthrough {
+    /// Add the incoming parameters, keeping the destination's value when both have one.
+    #[default]
+    Merge,
+    /// Add the incoming parameters, replacing the destination's value when both have one.
+    Override,
+    /// Drop the incoming query string.
+    Ignore,
+}
+
+/// Splits `dash?env=prod#cpu more args` into `dash more args`, its query string and its fragment.
+///
+/// Only the alias, the first word, can have a query string or fragment. A `?` in the arguments
+/// is left alone.
+pub fn split_link(link: &str) -> (String, &str, Option<&str>) {
+    let (alias, args) = link.split_at(link.find(' ').unwrap_or(link.len()));
+    let (rest, fragment) = match alias.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (alias, None),
+    };
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    (format!("{}{}", path, args), query, fragment)
+}
+
+/// Carry the incoming query string and fragment over to `destination`.
+///
+/// The fragment is only used when the destination doesn't have its own. Destinations
+/// that aren't URLs are returned as they are.
+pub fn apply_passthrough(
+    destination: &str,
+    query: &str,
+    fragment: Option<&str>,
+    policy: QueryPassthrough,
+) -> String {
+    let mut url = match Url::parse(destination) {
+        Ok(url) if !url.cannot_be_a_base() => url,
+        _ => return destination.to_owned(),
+    };
+
+    let incoming: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    if policy != QueryPassthrough::Ignore && !incoming.is_empty() {
+        let mut params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        for (key, value) in incoming {
+            match params.iter_mut().find(|(existing, _)| *existing == key) {
+                Some(param) if policy == QueryPassthrough::Override => param.1 = value,
+                Some(_) => {}
+                None => params.push((key, value)),
+            }
+        }
+        url.query_pairs_mut().clear().extend_pairs(params);
+    }
+
+    if let Some(fragment) = fragment {
+        if url.fragment().is_none() && !fragment.is_empty() {
+            url.set_fragment(Some(fragment));
+        }
+    }
+
+    url.to_string()
+}
+
+#[test]
+fn splits_links() {
+    assert_eq!(
+        ("dash".to_owned(), "env=prod", Some("cpu")),
+        split_link("dash?env=prod#cpu")
+    );
+    assert_eq!(("dash".to_owned(), "", None), split_link("dash"));
+    assert_eq!(
+        ("google is it?".to_owned(), "", None),
+        split_link("google is it?")
+    );
+}
+
+#[test]
+fn passes_query_and_fragment_through() {
+    let destination = "https://grafana.example.com/d/abc?env=dev&theme=dark";
+
+    assert_eq!(
+        "https://grafana.example.com/d/abc?env=dev&theme=dark&from=now#cpu",
+        apply_passthrough(
+            destination,
+            "env=prod&from=now",
+            Some("cpu"),
+            QueryPassthrough::Merge
+        )
+    );
+    assert_eq!(
+        "https://grafana.example.com/d/abc?env=prod&theme=dark&from=now",
+        apply_passthrough(
+            destination,
+            "env=prod&from=now",
+            None,
+            QueryPassthrough::Override
+        )
+    );
+    assert_eq!(
+        destination,
+        apply_passthrough(destination, "env=prod", None, QueryPassthrough::Ignore)
+    );
+    assert_eq!(
+        "https://example.com/#mine",
+        apply_passthrough(
+            "https://example.com/#mine",
+            "",
+            Some("cpu"),
+            QueryPassthrough::Merge
+        )
+    );
+}
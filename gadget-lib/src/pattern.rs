@@ -0,0 +1,191 @@
+use crate::prelude::{GadgetLibError, LibResult, RedirectModel};
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// How a redirect's alias is compared against the incoming alias.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AliasPattern {
+    /// The alias must be exactly the incoming alias.
+    #[default]
+    Exact,
+    /// `*` matches within a path segment, `**` matches across segments.
+    Glob,
+    /// A regular expression that must match the whole alias. Named groups are
+    /// available to the destination as `$name`.
+    Regex,
+}
+
+/// A pattern redirect that matched, with the values it captured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternMatch {
+    pub model: RedirectModel,
+    /// Every capture group in order. These are given to the destination ahead of any arguments.
+    pub captures: Vec<String>,
+    /// Capture groups that had a name.
+    pub named_captures: Vec<(String, String)>,
+}
+
+/// Turns an alias pattern into an anchored regex.
+pub fn compile_pattern(alias: &str, pattern: AliasPattern) -> LibResult<Option<Regex>> {
+    let expression = match pattern {
+        AliasPattern::Exact => return Ok(None),
+        AliasPattern::Regex => format!("^(?:{})$", alias),
+        AliasPattern::Glob => {
+            let mut expression = String::from("^");
+            let mut parts = alias.split("**").peekable();
+            while let Some(part) = parts.next() {
+                let segments: Vec<String> = part.split('*').map(regex::escape).collect();
+                expression.push_str(&segments.join("([^/]+)"));
+                if parts.peek().is_some() {
+                    expression.push_str("(.+)");
+                }
+            }
+            expression.push('$');
+            expression
+        }
+    };
+
+    match Regex::new(&expression) {
+        Ok(regex) => Ok(Some(regex)),
+        Err(e) => Err(GadgetLibError::InvalidPattern(format!("{}: {}", alias, e))),
+    }
+}
+
+/// All the pattern redirects of a backend, compiled once and ordered by precedence.
+///
+/// Patterns are tried by ascending `priority`, then by alias, and the first match wins.
+/// They are matched against the alias as it was given, without the alias normalization.
+#[derive(Debug, Default)]
+pub struct PatternSet {
+    /// Every pattern at once, missing when they couldn't be combined and are tried one by one.
+    set: Option<RegexSet>,
+    patterns: Vec<(Regex, RedirectModel)>,
+}
+
+impl PatternSet {
+    pub fn new(redirects: &[RedirectModel]) -> Self {
+        let mut patterns = Vec::new();
+        for redirect in redirects {
+            match compile_pattern(&redirect.alias, redirect.options.pattern) {
+                Ok(Some(regex)) => patterns.push((regex, redirect.clone())),
+                Ok(None) => {}
+                Err(e) => warn!("Skipping redirect {}: {}", redirect.public_ref, e),
+            }
+        }
+
+        patterns.sort_by(|(_, a), (_, b)| {
+            a.options
+                .priority
+                .cmp(&b.options.priority)
+                .then_with(|| a.alias.cmp(&b.alias))
+        });
+
+        let set = match RegexSet::new(patterns.iter().map(|(regex, _)| regex.as_str())) {
+            Ok(set) => Some(set),
+            Err(e) => {
+                warn!(
+                    "Unable to combine {} pattern redirects, trying them one by one: {}",
+                    patterns.len(),
+                    e
+                );
+                None
+            }
+        };
+
+        PatternSet { set, patterns }
+    }
+
+    pub fn find(&self, alias: &str) -> Option<PatternMatch> {
+        let index = match &self.set {
+            Some(set) => set.matches(alias).into_iter().next()?,
+            None => self
+                .patterns
+                .iter()
+                .position(|(regex, _)| regex.is_match(alias))?,
+        };
+        let (regex, model) = &self.patterns[index];
+        let found = regex.captures(alias)?;
+
+        let captures = found
+            .iter()
+            .skip(1)
+            .map(|x| x.map(|x| x.as_str().to_owned()).unwrap_or_default())
+            .collect();
+        let named_captures = regex
+            .capture_names()
+            .flatten()
+            .filter_map(|name| {
+                found
+                    .name(name)
+                    .map(|x| (name.to_owned(), x.as_str().to_owned()))
+            })
+            .collect();
+
+        Some(PatternMatch {
+            model: model.clone(),
+            captures,
+            named_captures,
+        })
+    }
+}
+
+#[test]
+fn glob_patterns_capture_segments() {
+    let regex = compile_pattern("PROJ-*", AliasPattern::Glob)
+        .unwrap()
+        .unwrap();
+    assert!(regex.is_match("PROJ-1234"));
+    assert!(!regex.is_match("PROJ-12/34"));
+
+    let regex = compile_pattern("src/**", AliasPattern::Glob)
+        .unwrap()
+        .unwrap();
+    assert!(regex.is_match("src/lib/mod.rs"));
+}
+
+#[test]
+fn lowest_priority_wins() {
+    use crate::prelude::RedirectOptions;
+
+    let model = |id: i32, alias: &str, pattern: AliasPattern, priority: i32| {
+        let options = RedirectOptions {
+            pattern,
+            priority,
+            ..Default::default()
+        };
+        RedirectModel::new(id, alias, "https://example.com", &options, None)
+    };
+
+    let set = PatternSet::new(&[
+        model(1, "*", AliasPattern::Glob, 10),
+        model(
+            2,
+            "(?P<project>[A-Z]+)-(?P<id>[0-9]+)",
+            AliasPattern::Regex,
+            0,
+        ),
+        model(3, "exact", AliasPattern::Exact, -10),
+    ]);
+
+    let found = set.find("PROJ-1234").unwrap();
+    assert_eq!(2, found.model.redirect_id);
+    assert_eq!(vec!["PROJ", "1234"], found.captures);
+    assert_eq!(
+        vec![
+            ("project".to_owned(), "PROJ".to_owned()),
+            ("id".to_owned(), "1234".to_owned())
+        ],
+        found.named_captures
+    );
+
+    assert_eq!(1, set.find("exact").unwrap().model.redirect_id);
+
+    let one_by_one = PatternSet {
+        set: None,
+        patterns: set.patterns.clone(),
+    };
+    assert_eq!(2, one_by_one.find("PROJ-1234").unwrap().model.redirect_id);
+    assert_eq!(1, one_by_one.find("exact").unwrap().model.redirect_id);
+}
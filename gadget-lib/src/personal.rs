@@ -0,0 +1,90 @@
+use crate::api::UserDetails;
+use crate::prelude::{GadgetLibError, LibResult};
+
+/// Aliases starting with `~username/` are in that user's personal namespace.
+pub const PERSONAL_PREFIX: char = '~';
+
+/// Links starting with `~/` are in the namespace of whoever follows them.
+const CURRENT_USER_PREFIX: &str = "~/";
+
+/// The user whose namespace `alias` is in, or `None` for shared aliases.
+pub fn namespace_owner(alias: &str) -> Option<&str> {
+    let rest = alias.strip_prefix(PERSONAL_PREFIX)?;
+    Some(match rest.split_once('/') {
+        Some((owner, _)) => owner,
+        None => rest,
+    })
+}
+
+pub fn is_personal(alias: &str) -> bool {
+    namespace_owner(alias).is_some()
+}
+
+/// Puts a `~/` link into `user`'s namespace, so `~/notes` is `~alice/notes` for alice.
+///
+/// Returns `None` for `~/` links when there isn't a user to put them under.
+pub fn expand(query: &str, user: Option<&UserDetails>) -> Option<String> {
+    match query.strip_prefix(CURRENT_USER_PREFIX) {
+        Some(rest) => user.map(|user| format!("{}{}/{}", PERSONAL_PREFIX, user.username, rest)),
+        None => Some(query.to_owned()),
+    }
+}
+
+/// The alias `user` means by `alias`, when they are allowed to create or change it.
+///
+/// Anyone can change shared aliases, but only the owner of a personal namespace can change
+/// the aliases in it.
+pub fn owned_alias(alias: &str, user: Option<&UserDetails>) -> LibResult<String> {
+    let expanded =
+        expand(alias, user).ok_or_else(|| GadgetLibError::PersonalAliasDenied(alias.to_owned()))?;
+    match namespace_owner(&expanded) {
+        Some(owner) if Some(owner) != user.map(|user| user.username.as_str()) => {
+            Err(GadgetLibError::PersonalAliasDenied(expanded))
+        }
+        _ => Ok(expanded),
+    }
+}
+
+/// Whether `alias` is listed for `user`. Personal aliases are only listed for their owner,
+/// and only when `include_personal` asks for them.
+pub fn is_listed(alias: &str, user: Option<&UserDetails>, include_personal: bool) -> bool {
+    match namespace_owner(alias) {
+        None => true,
+        Some(owner) => include_personal && matches!(user, Some(user) if user.username == owner),
+    }
+}
+
+#[test]
+fn expands_the_current_users_namespace() {
+    let alice = UserDetails {
+        username: "alice".to_owned(),
+        groups: Vec::new(),
+    };
+
+    assert_eq!(Some("alice"), namespace_owner("~alice/notes"));
+    assert_eq!(Some("alice"), namespace_owner("~alice"));
+    assert_eq!(None, namespace_owner("notes/~alice"));
+
+    assert_eq!(
+        Some("~alice/notes 1".to_owned()),
+        expand("~/notes 1", Some(&alice))
+    );
+    assert_eq!(Some("notes".to_owned()), expand("notes", None));
+    assert_eq!(None, expand("~/notes", None));
+
+    assert_eq!(
+        "~alice/notes",
+        owned_alias("~/notes", Some(&alice)).unwrap()
+    );
+    assert_eq!("notes", owned_alias("notes", None).unwrap());
+    assert!(matches!(
+        owned_alias("~bob/notes", Some(&alice)),
+        Err(GadgetLibError::PersonalAliasDenied(alias)) if alias == "~bob/notes"
+    ));
+    assert!(owned_alias("~/notes", None).is_err());
+
+    assert!(is_listed("notes", None, false));
+    assert!(!is_listed("~alice/notes", Some(&alice), false));
+    assert!(is_listed("~alice/notes", Some(&alice), true));
+    assert!(!is_listed("~bob/notes", Some(&alice), true));
+}
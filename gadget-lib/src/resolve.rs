@@ -0,0 +1,893 @@
+use crate::api::{ResolveBatchItem, ResolvedRedirect, UserDetails};
+use crate::passthrough::{apply_passthrough, split_link};
+use crate::personal;
+use crate::prelude::*;
+use crate::schedule::active_destination;
+use crate::weighted::choose_destination;
+use crate::Evaluation;
+use chrono::Utc;
+use tracing::debug;
+use url::Url;
+
+/// Destinations starting with this scheme point at another alias instead of a URL.
+pub const ALIAS_SCHEME: &str = "alias:";
+
+/// How many `alias:` hops are followed before giving up.
+pub const DEFAULT_MAX_ALIAS_DEPTH: usize = 5;
+
+/// Returns the alias a destination points at, when it's an `alias:` destination.
+///
+/// Destinations whose target alias is itself a template (`alias:$1`) can't be
+/// known ahead of time, so they return `None`.
+pub fn target_alias(destination: &str) -> Option<&str> {
+    let rest = destination.strip_prefix(ALIAS_SCHEME)?;
+    let alias = rest.trim().split(' ').next()?;
+    if alias.is_empty() || alias.contains('$') || alias.contains('{') {
+        None
+    } else {
+        Some(alias)
+    }
+}
+
+/// Walks the `alias:` chain starting at `alias` => `destination`, and returns the
+/// chain if it ever comes back to an alias it already visited.
+///
+/// `lookup` returns the stored destination for an alias.
+pub fn find_alias_loop<F>(alias: &str, destination: &str, lookup: F) -> Option<Vec<String>>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut chain = vec![alias.to_owned()];
+    let mut next = target_alias(destination).map(|x| x.to_owned());
+
+    while let Some(target) = next {
+        if chain.contains(&target) {
+            chain.push(target);
+            return Some(chain);
+        }
+
+        next = lookup(&target).and_then(|dest| target_alias(&dest).map(|x| x.to_owned()));
+        chain.push(target);
+    }
+
+    None
+}
+
+/// Placeholder in a destination that receives the part of the path that didn't match the alias.
+pub const PATH_PLACEHOLDER: &str = "$path";
+
+/// A redirect that matched an incoming alias, and what the match left over for the destination.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AliasMatch {
+    pub model: RedirectModel,
+    /// Part of the alias after the matched prefix, including its leading `/`.
+    pub path_suffix: String,
+    /// Values captured by a pattern alias, in order.
+    pub captures: Vec<String>,
+    /// Values captured by named groups of a regex alias.
+    pub named_captures: Vec<(String, String)>,
+}
+
+impl AliasMatch {
+    fn new(model: RedirectModel, path_suffix: String) -> Self {
+        AliasMatch {
+            model,
+            path_suffix,
+            captures: Vec::new(),
+            named_captures: Vec::new(),
+        }
+    }
+
+    /// Evaluate the matched redirect for `query`, where the first word of `query` is the alias.
+    ///
+    /// Pattern captures are given to the destination ahead of the rest of the query.
+    pub fn evaluate(&self, query: &str) -> String {
+        self.explain(query).destination
+    }
+
+    /// Like [`AliasMatch::evaluate`], but also says which arm was used and what looked wrong.
+    pub fn explain(&self, query: &str) -> Evaluation {
+        let mut inputs: Vec<&str> = query.split(' ').collect();
+        for (index, capture) in self.captures.iter().enumerate() {
+            inputs.insert(index + 1, capture);
+        }
+
+        let mut evaluation =
+            AliasRedirect::from(self.model.clone()).explain_query(&inputs.join(" "));
+
+        let mut named_captures: Vec<&(String, String)> = self.named_captures.iter().collect();
+        named_captures.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        for (name, value) in named_captures {
+            evaluation.destination = evaluation.destination.replace(&format!("${}", name), value);
+        }
+
+        if !self.path_suffix.is_empty()
+            && !evaluation.destination.contains(PATH_PLACEHOLDER)
+            && !self.model.options.path_passthrough
+        {
+            evaluation.warnings.push(format!(
+                "`{}` matched `{}`, the rest of the path `{}` isn't used",
+                query.split(' ').next().unwrap_or_default(),
+                self.model.alias,
+                self.path_suffix
+            ));
+        }
+
+        evaluation.destination = apply_path_suffix(
+            evaluation.destination,
+            &self.path_suffix,
+            self.model.options.path_passthrough,
+        );
+        evaluation
+    }
+}
+
+/// Find the redirect for `redirect_ref`. Exact aliases win, then pattern aliases in
+/// order of precedence, then the longest `/` separated prefix that exists.
+pub fn find_match<'a>(
+    backend: &dyn Backend<'a>,
+    namespace: &str,
+    redirect_ref: &str,
+) -> LibResult<Option<AliasMatch>> {
+    if let Some(model) = backend.get_redirect(namespace, redirect_ref)? {
+        return Ok(Some(AliasMatch::new(model, String::new())));
+    }
+
+    if let Some(found) = backend.find_pattern(namespace, redirect_ref)? {
+        return Ok(Some(AliasMatch {
+            model: found.model,
+            path_suffix: String::new(),
+            captures: found.captures,
+            named_captures: found.named_captures,
+        }));
+    }
+
+    Ok(find_longest_prefix(backend, namespace, redirect_ref)?
+        .map(|(model, suffix)| AliasMatch::new(model, suffix)))
+}
+
+/// Find the redirect for `redirect_ref`, falling back to the longest `/` separated
+/// prefix that exists. The unmatched suffix (including its leading `/`) is returned
+/// alongside the redirect, and is empty on an exact match.
+pub fn find_longest_prefix<'a>(
+    backend: &dyn Backend<'a>,
+    namespace: &str,
+    redirect_ref: &str,
+) -> LibResult<Option<(RedirectModel, String)>> {
+    let mut prefix = redirect_ref;
+
+    loop {
+        if let Some(model) = backend.get_redirect(namespace, prefix)? {
+            return Ok(Some((model, redirect_ref[prefix.len()..].to_owned())));
+        }
+
+        match prefix.rfind('/') {
+            Some(index) if index > 0 => prefix = &prefix[..index],
+            _ => return Ok(None),
+        }
+    }
+}
+
+/// Put the unmatched suffix into the destination, either where `$path` is or, when
+/// `passthrough` is set, on the end of the destination's path.
+fn apply_path_suffix(destination: String, suffix: &str, passthrough: bool) -> String {
+    if destination.contains(PATH_PLACEHOLDER) {
+        return destination.replace(PATH_PLACEHOLDER, suffix);
+    }
+
+    if !passthrough || suffix.is_empty() {
+        return destination;
+    }
+
+    if destination.starts_with(ALIAS_SCHEME) {
+        return match destination.split_once(' ') {
+            Some((target, rest)) => format!("{}{} {}", target, suffix, rest),
+            None => format!("{}{}", destination, suffix),
+        };
+    }
+
+    match Url::parse(&destination) {
+        Ok(mut url) if !url.cannot_be_a_base() => {
+            let path = format!("{}{}", url.path().trim_end_matches('/'), suffix);
+            url.set_path(&path);
+            url.to_string()
+        }
+        _ => format!("{}{}", destination, suffix),
+    }
+}
+
+/// Where a query ended up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolution {
+    /// The redirect the query matched, before any `alias:` destinations were followed.
+    pub redirect: RedirectModel,
+    pub destination: String,
+    /// The template arm of the last redirect in the chain, the one that gave the destination.
+    pub arm: String,
+    /// Warnings from every redirect along the chain.
+    pub warnings: Vec<String>,
+    /// The weighted destination that was picked, when a redirect along the chain has them.
+    pub chosen: Option<String>,
+    /// Every redirect along the chain, in the order they were followed, starting with `redirect`.
+    pub followed: Vec<RedirectModel>,
+}
+
+impl Resolution {
+    /// Carry an incoming query string and fragment over to the destination, as the
+    /// matched redirect's `query_passthrough` says.
+    pub fn pass_through(&mut self, query: &str, fragment: Option<&str>) {
+        self.destination = apply_passthrough(
+            &self.destination,
+            query,
+            fragment,
+            self.redirect.options.query_passthrough,
+        );
+    }
+}
+
+/// Resolve a whole link, like `dash?env=prod#cpu`, carrying its query string and fragment
+/// over to the destination.
+pub fn resolve_link<'a>(
+    backend: &dyn Backend<'a>,
+    namespace: &str,
+    link: &str,
+    max_depth: usize,
+    user: Option<&UserDetails>,
+) -> LibResult<Option<Resolution>> {
+    let (query, query_string, fragment) = split_link(link);
+    let mut resolution = resolve_destination(backend, namespace, &query, max_depth, user)?;
+    if let Some(resolution) = resolution.as_mut() {
+        resolution.pass_through(query_string, fragment);
+    }
+    Ok(resolution)
+}
+
+/// Evaluate `query` against the backend's `namespace`, following `alias:` destinations until
+/// a real destination is found. Every alias along the chain is in the same namespace.
+///
+/// Each alias is matched with [`find_match`], and uses one of its weighted destinations,
+/// picked for `user`, or else the destination its schedule says is active.
+/// `~/` aliases are looked up in `user`'s personal namespace.
+/// Returns `Ok(None)` when any alias along the way doesn't exist or isn't visible to `user`, and
+/// [`GadgetLibError::RedirectExpired`] when one of them has expired but hasn't been swept yet.
+#[tracing::instrument(skip(backend, user), fields(chosen))]
+pub fn resolve_destination<'a>(
+    backend: &dyn Backend<'a>,
+    namespace: &str,
+    query: &str,
+    max_depth: usize,
+    user: Option<&UserDetails>,
+) -> LibResult<Option<Resolution>> {
+    let mut query = query.to_owned();
+    let mut followed: Vec<RedirectModel> = Vec::new();
+    let mut warnings = Vec::new();
+    let mut chosen = None;
+
+    for _ in 0..=max_depth {
+        query = match personal::expand(&query, user) {
+            Some(expanded) => expanded,
+            None => return Ok(None),
+        };
+        let redirect_ref = query.split(' ').next().unwrap_or_default();
+        let mut found = match find_match(backend, namespace, redirect_ref)? {
+            Some(found) if found.model.visible_to(user) => found,
+            _ => return Ok(None),
+        };
+        let now = Utc::now().naive_utc();
+        if found.model.is_expired(now) {
+            return Err(GadgetLibError::RedirectExpired(found.model.alias));
+        }
+        let username = user.map(|user| user.username.as_str());
+        found.model.destination = match choose_destination(&found.model, username) {
+            Some(weighted) => {
+                debug!("Picked {} for {}", weighted.destination, redirect_ref);
+                tracing::Span::current().record("chosen", &weighted.destination.as_str());
+                chosen.get_or_insert(weighted.destination.clone());
+                weighted.destination.clone()
+            }
+            None => {
+                active_destination(&found.model.destination, &found.model.options.schedule, now)
+                    .to_owned()
+            }
+        };
+
+        let evaluation = found.explain(&query);
+        warnings.extend(evaluation.warnings);
+        followed.push(found.model);
+        match evaluation.destination.strip_prefix(ALIAS_SCHEME) {
+            Some(next) => {
+                debug!("Following {} to {}", redirect_ref, next);
+                query = next.trim().to_owned();
+            }
+            None => {
+                if Url::parse(&evaluation.destination).is_err() {
+                    warnings.push(format!("`{}` isn't a valid URL", evaluation.destination));
+                }
+                return Ok(Some(Resolution {
+                    redirect: followed[0].clone(),
+                    destination: evaluation.destination,
+                    arm: evaluation.arm,
+                    warnings,
+                    chosen,
+                    followed,
+                }));
+            }
+        }
+    }
+
+    Err(GadgetLibError::AliasDepthExceeded(query))
+}
+
+/// Most queries a single batch can resolve.
+pub const MAX_RESOLVE_BATCH: usize = 100;
+
+/// Resolve every link with [`resolve_link`]. A link that fails doesn't stop the others.
+pub fn resolve_batch<'a>(
+    backend: &dyn Backend<'a>,
+    namespace: &str,
+    queries: Vec<String>,
+    max_depth: usize,
+    user: Option<&UserDetails>,
+) -> Vec<ResolveBatchItem> {
+    queries
+        .into_iter()
+        .map(|query| {
+            let (resolved, error) = match resolve_link(backend, namespace, &query, max_depth, user)
+            {
+                Ok(resolution) => (
+                    resolution.map(|resolution| ResolvedRedirect::new(&query, resolution)),
+                    None,
+                ),
+                Err(GadgetLibError::RedirectDoesNotExists(_)) => (None, None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+            ResolveBatchItem {
+                query,
+                resolved,
+                error,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn destination_for(backend: &InMemoryBackend, query: &str) -> Option<String> {
+    resolve_destination(
+        backend,
+        DEFAULT_NAMESPACE,
+        query,
+        DEFAULT_MAX_ALIAS_DEPTH,
+        None,
+    )
+    .unwrap()
+    .map(|x| x.destination)
+}
+
+#[test]
+fn follows_alias_chain_with_arguments() {
+    let backend = InMemoryBackend::new(Default::default());
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "docs/api",
+            "https://docs.example.com/api{/$1}",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "api",
+            "alias:docs/api",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    let resolution = resolve_destination(
+        &backend,
+        DEFAULT_NAMESPACE,
+        "api users",
+        DEFAULT_MAX_ALIAS_DEPTH,
+        None,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!("api", resolution.redirect.alias);
+    assert_eq!("https://docs.example.com/api/users", resolution.destination);
+    assert_eq!("https://docs.example.com/api/$1", resolution.arm);
+    assert!(resolution.warnings.is_empty());
+    assert_eq!(None, destination_for(&backend, "missing"));
+}
+
+#[test]
+fn stops_following_past_max_depth() {
+    let backend = InMemoryBackend::new(Default::default());
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "c",
+            "https://example.com",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "b",
+            "alias:c",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "a",
+            "alias:b",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    assert!(
+        resolve_destination(&backend, DEFAULT_NAMESPACE, "a", 2, None)
+            .unwrap()
+            .is_some()
+    );
+    assert!(matches!(
+        resolve_destination(&backend, DEFAULT_NAMESPACE, "a", 1, None),
+        Err(GadgetLibError::AliasDepthExceeded(_))
+    ));
+
+    let results = resolve_batch(
+        &backend,
+        DEFAULT_NAMESPACE,
+        vec!["a".to_owned(), "missing".to_owned()],
+        1,
+        None,
+    );
+    assert!(results[0].resolved.is_none() && results[0].error.is_some());
+    assert!(results[1].resolved.is_none() && results[1].error.is_none());
+}
+
+#[test]
+fn rejects_alias_loops() {
+    let backend = InMemoryBackend::new(Default::default());
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "a",
+            "alias:b",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "b",
+            "alias:c",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    assert!(matches!(
+        backend.create_redirect(
+            DEFAULT_NAMESPACE,
+            "c",
+            "alias:a",
+            &Default::default(),
+            "bob"
+        ),
+        Err(GadgetLibError::AliasLoop(_))
+    ));
+    assert!(matches!(
+        backend.update_redirect(DEFAULT_NAMESPACE, "b", "alias:a", None, "bob"),
+        Err(GadgetLibError::AliasLoop(_))
+    ));
+    assert!(matches!(
+        backend.create_redirect(
+            DEFAULT_NAMESPACE,
+            "d",
+            "alias:d",
+            &Default::default(),
+            "bob"
+        ),
+        Err(GadgetLibError::AliasLoop(_))
+    ));
+}
+
+#[test]
+fn falls_back_to_longest_prefix() {
+    let backend = InMemoryBackend::new(Default::default());
+    let passthrough = RedirectOptions {
+        path_passthrough: true,
+        ..Default::default()
+    };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "docs",
+            "https://docs.example.com/",
+            &passthrough,
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "wiki",
+            "https://wiki.example.com/",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "src",
+            "https://git.example.com/tree/main$path?plain=1",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    assert_eq!(
+        Some("https://docs.example.com/runbooks/db".to_owned()),
+        destination_for(&backend, "docs/runbooks/db")
+    );
+    assert_eq!(
+        Some("https://wiki.example.com/".to_owned()),
+        destination_for(&backend, "wiki/some/page")
+    );
+    assert_eq!(
+        Some("https://git.example.com/tree/main/lib/mod.rs?plain=1".to_owned()),
+        destination_for(&backend, "src/lib/mod.rs")
+    );
+    assert_eq!(None, destination_for(&backend, "other/docs"));
+
+    let resolution = resolve_destination(
+        &backend,
+        DEFAULT_NAMESPACE,
+        "wiki/some/page",
+        DEFAULT_MAX_ALIAS_DEPTH,
+        None,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(1, resolution.warnings.len());
+}
+
+#[test]
+fn resolves_links_with_query_strings() {
+    let backend = InMemoryBackend::new(Default::default());
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "dash",
+            "https://grafana.example.com/d/abc?env=dev",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    let resolution = resolve_link(
+        &backend,
+        DEFAULT_NAMESPACE,
+        "dash?env=prod&from=now#cpu",
+        DEFAULT_MAX_ALIAS_DEPTH,
+        None,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!(
+        "https://grafana.example.com/d/abc?env=dev&from=now#cpu",
+        resolution.destination
+    );
+}
+
+#[test]
+fn exact_aliases_win_over_patterns() {
+    use crate::pattern::AliasPattern;
+
+    let backend = InMemoryBackend::new(Default::default());
+    let jira = RedirectOptions {
+        pattern: AliasPattern::Regex,
+        ..Default::default()
+    };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "(?P<key>[A-Z]+-[0-9]+)",
+            "https://jira.example.com/browse/{$key{?focusedCommentId=$2}}",
+            &jira,
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "OPS-1",
+            "https://ops.example.com",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    assert_eq!(
+        Some("https://jira.example.com/browse/PROJ-1234".to_owned()),
+        destination_for(&backend, "PROJ-1234")
+    );
+    assert_eq!(
+        Some("https://jira.example.com/browse/PROJ-1234?focusedCommentId=99".to_owned()),
+        destination_for(&backend, "PROJ-1234 99")
+    );
+    assert_eq!(
+        Some("https://ops.example.com".to_owned()),
+        destination_for(&backend, "OPS-1")
+    );
+
+    let invalid = RedirectOptions {
+        pattern: AliasPattern::Regex,
+        ..Default::default()
+    };
+    assert!(matches!(
+        backend.create_redirect(
+            DEFAULT_NAMESPACE,
+            "(",
+            "https://example.com",
+            &invalid,
+            "bob"
+        ),
+        Err(GadgetLibError::InvalidPattern(_))
+    ));
+}
+
+#[test]
+fn expired_redirects_do_not_resolve() {
+    let backend = InMemoryBackend::new(Default::default());
+    let one_time = RedirectOptions {
+        max_uses: Some(1),
+        ..Default::default()
+    };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "once",
+            "https://example.com/once",
+            &one_time,
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "latest",
+            "alias:once",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    let resolution = resolve_destination(
+        &backend,
+        DEFAULT_NAMESPACE,
+        "latest",
+        DEFAULT_MAX_ALIAS_DEPTH,
+        None,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!("https://example.com/once", resolution.destination);
+    let followed: Vec<&str> = resolution
+        .followed
+        .iter()
+        .map(|redirect| redirect.alias.as_str())
+        .collect();
+    assert_eq!(vec!["latest", "once"], followed);
+    let now = Utc::now().naive_utc();
+    for redirect in &resolution.followed {
+        assert!(backend
+            .use_redirect(DEFAULT_NAMESPACE, &redirect.public_ref, now)
+            .unwrap());
+    }
+    assert!(!backend
+        .use_redirect(DEFAULT_NAMESPACE, &resolution.followed[1].public_ref, now)
+        .unwrap());
+
+    assert!(matches!(
+        resolve_destination(&backend, DEFAULT_NAMESPACE, "latest", DEFAULT_MAX_ALIAS_DEPTH, None),
+        Err(GadgetLibError::RedirectExpired(alias)) if alias == "once"
+    ));
+}
+
+#[test]
+fn reports_the_chosen_destination() {
+    use crate::weighted::{Selection, WeightedDestination};
+
+    let backend = InMemoryBackend::new(Default::default());
+    let split = RedirectOptions {
+        destinations: vec![
+            WeightedDestination {
+                destination: "https://old-wiki.example.com/{$1}".to_owned(),
+                weight: 0,
+            },
+            WeightedDestination {
+                destination: "https://new-wiki.example.com/{$1}".to_owned(),
+                weight: 3,
+            },
+        ],
+        selection: Selection::Sticky,
+        ..Default::default()
+    };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "wiki",
+            "https://wiki.example.com",
+            &split,
+            "bob",
+        )
+        .unwrap();
+
+    let user = UserDetails {
+        username: "alice".to_owned(),
+        groups: Vec::new(),
+    };
+    let resolution = resolve_destination(
+        &backend,
+        DEFAULT_NAMESPACE,
+        "wiki Home",
+        DEFAULT_MAX_ALIAS_DEPTH,
+        Some(&user),
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!("https://new-wiki.example.com/Home", resolution.destination);
+    assert_eq!(
+        Some("https://new-wiki.example.com/{$1}".to_owned()),
+        resolution.chosen
+    );
+    let anonymous = resolve_destination(
+        &backend,
+        DEFAULT_NAMESPACE,
+        "wiki",
+        DEFAULT_MAX_ALIAS_DEPTH,
+        None,
+    )
+    .unwrap()
+    .unwrap();
+    assert_eq!("https://new-wiki.example.com/", anonymous.destination);
+}
+
+#[test]
+fn hides_restricted_redirects() {
+    use crate::suggest::suggest_aliases;
+    use crate::visibility::Visibility;
+
+    let backend = InMemoryBackend::new(Default::default());
+    let restricted = RedirectOptions {
+        visibility: Visibility::Restricted {
+            users: Vec::new(),
+            groups: vec!["sre".to_owned()],
+        },
+        ..Default::default()
+    };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "oncall",
+            "https://pager.example.com",
+            &restricted,
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "pager",
+            "alias:oncall",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    let member = UserDetails {
+        username: "alice".to_owned(),
+        groups: vec!["sre".to_owned()],
+    };
+    let outsider = UserDetails {
+        username: "carol".to_owned(),
+        groups: vec!["dev".to_owned()],
+    };
+    for query in ["oncall", "pager"] {
+        assert!(resolve_destination(
+            &backend,
+            DEFAULT_NAMESPACE,
+            query,
+            DEFAULT_MAX_ALIAS_DEPTH,
+            Some(&member)
+        )
+        .unwrap()
+        .is_some());
+        assert!(resolve_destination(
+            &backend,
+            DEFAULT_NAMESPACE,
+            query,
+            DEFAULT_MAX_ALIAS_DEPTH,
+            Some(&outsider)
+        )
+        .unwrap()
+        .is_none());
+        assert!(destination_for(&backend, query).is_none());
+    }
+
+    let suggestions =
+        suggest_aliases(&backend, DEFAULT_NAMESPACE, "oncal", 10, Some(&outsider)).unwrap();
+    assert!(suggestions.is_empty());
+    let suggestions =
+        suggest_aliases(&backend, DEFAULT_NAMESPACE, "oncal", 10, Some(&member)).unwrap();
+    assert_eq!("oncall", suggestions[0].alias);
+}
+
+#[test]
+fn resolves_the_current_users_namespace() {
+    let backend = InMemoryBackend::new(Default::default());
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "~alice/notes",
+            "https://notes.example.com/alice",
+            &Default::default(),
+            "alice",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "notes",
+            "alias:~/notes",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    let alice = UserDetails {
+        username: "alice".to_owned(),
+        groups: Vec::new(),
+    };
+    let bob = UserDetails {
+        username: "bob".to_owned(),
+        groups: Vec::new(),
+    };
+    for query in ["~/notes", "notes"] {
+        let resolution = resolve_destination(
+            &backend,
+            DEFAULT_NAMESPACE,
+            query,
+            DEFAULT_MAX_ALIAS_DEPTH,
+            Some(&alice),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!("https://notes.example.com/alice", resolution.destination);
+        assert!(resolve_destination(
+            &backend,
+            DEFAULT_NAMESPACE,
+            query,
+            DEFAULT_MAX_ALIAS_DEPTH,
+            Some(&bob)
+        )
+        .unwrap()
+        .is_none());
+        assert!(destination_for(&backend, query).is_none());
+    }
+    assert_eq!(
+        Some("https://notes.example.com/alice".to_owned()),
+        destination_for(&backend, "~alice/notes")
+    );
+}
@@ -0,0 +1,223 @@
+use crate::prelude::{GadgetLibError, LibResult, RedirectModel};
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Longest a change can take to repeat, a hundred years.
+pub const MAX_EVERY: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// A destination a redirect switches to at `starts_at`.
+///
+/// With `every`, the switch happens again every that many seconds, so two changes
+/// a week apart that both repeat every two weeks make a rotation.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ScheduledChange {
+    pub destination: String,
+    /// When the destination takes over, in UTC.
+    pub starts_at: NaiveDateTime,
+    /// How many seconds until the destination takes over again, up to [`MAX_EVERY`].
+    #[serde(default)]
+    pub every: Option<u64>,
+}
+
+impl ScheduledChange {
+    /// Changes that were stored before `every` was checked can still have one that is out of
+    /// range, they are treated like they don't repeat.
+    fn period(&self) -> Option<Duration> {
+        match self.every {
+            Some(every) if every > 0 && every <= MAX_EVERY => {
+                i64::try_from(every).ok().map(Duration::seconds)
+            }
+            _ => None,
+        }
+    }
+
+    /// The last time, up to `now`, this change took over.
+    pub fn last_start(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        if self.starts_at > now {
+            return None;
+        }
+
+        match self.period() {
+            Some(period) => {
+                let elapsed = (now - self.starts_at).num_seconds() / period.num_seconds();
+                self.starts_at
+                    .checked_add_signed(Duration::seconds(elapsed * period.num_seconds()))
+            }
+            None => Some(self.starts_at),
+        }
+    }
+
+    /// The next time, after `now`, this change takes over.
+    pub fn next_start(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        match (self.last_start(now), self.period()) {
+            (None, _) => Some(self.starts_at),
+            (Some(last), Some(period)) => last.checked_add_signed(period),
+            (Some(_), None) => None,
+        }
+    }
+}
+
+/// Every change has to repeat after more than zero seconds and at most [`MAX_EVERY`].
+pub fn check_schedule(schedule: &[ScheduledChange]) -> LibResult<()> {
+    match schedule
+        .iter()
+        .find(|change| matches!(change.every, Some(every) if every == 0 || every > MAX_EVERY))
+    {
+        Some(change) => Err(GadgetLibError::InvalidSchedule(format!(
+            "{} can't repeat every {} seconds, it has to be between 1 and {}",
+            change.destination,
+            change.every.unwrap_or_default(),
+            MAX_EVERY
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// The change that took over most recently, up to `now`, and when it did.
+pub fn latest_change(
+    schedule: &[ScheduledChange],
+    now: NaiveDateTime,
+) -> Option<(NaiveDateTime, &ScheduledChange)> {
+    schedule
+        .iter()
+        .filter_map(|change| change.last_start(now).map(|start| (start, change)))
+        .max_by_key(|(start, _)| *start)
+}
+
+/// The destination that is active at `now`, from the change that took over most recently.
+/// Before any change takes over, it's the redirect's own destination.
+pub fn active_destination<'a>(
+    destination: &'a str,
+    schedule: &'a [ScheduledChange],
+    now: NaiveDateTime,
+) -> &'a str {
+    latest_change(schedule, now)
+        .map(|(_, change)| change.destination.as_str())
+        .unwrap_or(destination)
+}
+
+/// A destination switch that hasn't happened yet.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct UpcomingChange {
+    pub alias: String,
+    pub destination: String,
+    pub starts_at: NaiveDateTime,
+}
+
+/// The next switch of every scheduled change, soonest first.
+pub fn upcoming_changes(redirects: &[RedirectModel], now: NaiveDateTime) -> Vec<UpcomingChange> {
+    let mut upcoming: Vec<UpcomingChange> = redirects
+        .iter()
+        .flat_map(|redirect| {
+            redirect.options.schedule.iter().filter_map(move |change| {
+                change.next_start(now).map(|starts_at| UpcomingChange {
+                    alias: redirect.alias.clone(),
+                    destination: change.destination.clone(),
+                    starts_at,
+                })
+            })
+        })
+        .collect();
+    upcoming.sort_by_key(|change| change.starts_at);
+    upcoming
+}
+
+#[cfg(test)]
+fn at(time: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M").unwrap()
+}
+
+#[test]
+fn picks_the_latest_change() {
+    let schedule = vec![
+        ScheduledChange {
+            destination: "https://example.com/august".to_owned(),
+            starts_at: at("2022-08-01 00:00"),
+            every: None,
+        },
+        ScheduledChange {
+            destination: "https://example.com/september".to_owned(),
+            starts_at: at("2022-09-01 00:00"),
+            every: None,
+        },
+    ];
+    let default = "https://example.com/";
+
+    assert_eq!(
+        default,
+        active_destination(default, &schedule, at("2022-07-31 23:59"))
+    );
+    assert_eq!(
+        "https://example.com/august",
+        active_destination(default, &schedule, at("2022-08-01 00:00"))
+    );
+    assert_eq!(
+        "https://example.com/september",
+        active_destination(default, &schedule, at("2023-01-01 00:00"))
+    );
+}
+
+#[test]
+fn rotates_recurring_changes() {
+    let two_weeks = 14 * 24 * 60 * 60;
+    let schedule = vec![
+        ScheduledChange {
+            destination: "https://example.com/alice".to_owned(),
+            starts_at: at("2022-08-01 09:00"),
+            every: Some(two_weeks),
+        },
+        ScheduledChange {
+            destination: "https://example.com/bob".to_owned(),
+            starts_at: at("2022-08-08 09:00"),
+            every: Some(two_weeks),
+        },
+    ];
+    let default = "https://example.com/";
+
+    assert_eq!(
+        "https://example.com/alice",
+        active_destination(default, &schedule, at("2022-08-05 12:00"))
+    );
+    assert_eq!(
+        "https://example.com/bob",
+        active_destination(default, &schedule, at("2022-08-12 12:00"))
+    );
+    assert_eq!(
+        "https://example.com/alice",
+        active_destination(default, &schedule, at("2022-08-15 09:00"))
+    );
+    assert_eq!(
+        Some(at("2022-08-22 09:00")),
+        schedule[1].next_start(at("2022-08-15 09:00"))
+    );
+}
+
+#[test]
+fn rejects_out_of_range_repeats() {
+    let change = |every| ScheduledChange {
+        destination: "https://example.com/".to_owned(),
+        starts_at: at("2022-08-01 09:00"),
+        every: Some(every),
+    };
+
+    assert!(check_schedule(&[change(60)]).is_ok());
+    assert!(check_schedule(&[change(MAX_EVERY)]).is_ok());
+    assert!(check_schedule(&[change(0)]).is_err());
+    assert!(check_schedule(&[change(60), change(MAX_EVERY + 1)]).is_err());
+
+    // Stored before they were checked, they don't repeat rather than overflow.
+    for every in [MAX_EVERY + 1, 10_000_000_000_000_000, u64::MAX] {
+        let huge = change(every);
+        assert_eq!(
+            Some(huge.starts_at),
+            huge.last_start(at("2022-09-01 00:00"))
+        );
+        assert_eq!(None, huge.next_start(at("2022-09-01 00:00")));
+    }
+    let mut last = change(MAX_EVERY);
+    last.starts_at = chrono::NaiveDate::from_ymd_opt(262_100, 1, 1)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .unwrap();
+    assert_eq!(None, last.next_start(last.starts_at));
+}
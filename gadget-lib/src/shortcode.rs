@@ -0,0 +1,214 @@
+use crate::normalize::AliasNormalization;
+use crate::personal::PERSONAL_PREFIX;
+use crate::prelude::{Backend, GadgetLibError, LibResult, RedirectModel, RedirectOptions};
+use tracing::debug;
+
+pub const DEFAULT_SHORT_CODE_LENGTH: usize = 6;
+/// Lower case letters and digits, without the ones that are easy to mix up (`0`/`o`, `1`/`l`/`i`).
+pub const DEFAULT_SHORT_CODE_ALPHABET: &str = "23456789abcdefghjkmnpqrstuvwxyz";
+
+/// How many codes are tried before giving up, when the ones generated are already taken.
+const MAX_ATTEMPTS: usize = 10;
+
+/// Generates aliases for redirects that are created without one.
+#[derive(Debug, Clone)]
+pub struct ShortCodes {
+    length: usize,
+    alphabet: Vec<char>,
+    host: Option<String>,
+}
+
+impl Default for ShortCodes {
+    fn default() -> Self {
+        ShortCodes::new(DEFAULT_SHORT_CODE_LENGTH, DEFAULT_SHORT_CODE_ALPHABET)
+            .expect("The default short codes to be valid")
+    }
+}
+
+impl ShortCodes {
+    /// The alphabet needs at least two characters, none of which can be a path, query or
+    /// fragment separator, or the prefix of personal links. Characters the default alias
+    /// normalization treats as the same, like `a` and `A` or `-` and `_`, can't both be used.
+    pub fn new(length: usize, alphabet: &str) -> LibResult<Self> {
+        let mut chars: Vec<char> = alphabet.chars().collect();
+        chars.sort_unstable();
+        chars.dedup();
+
+        if length == 0 {
+            return Err(GadgetLibError::InvalidShortCode(
+                "length must be at least 1".to_owned(),
+            ));
+        }
+        if chars.len() < 2 {
+            return Err(GadgetLibError::InvalidShortCode(format!(
+                "alphabet {:?} needs at least two characters",
+                alphabet
+            )));
+        }
+        if let Some(c) = chars.iter().find(|c| {
+            c.is_whitespace() || c.is_control() || "/?#%+".contains(**c) || **c == PERSONAL_PREFIX
+        }) {
+            return Err(GadgetLibError::InvalidShortCode(format!(
+                "alphabet can't contain {:?}",
+                c
+            )));
+        }
+
+        let normalization = AliasNormalization::default();
+        for (i, first) in chars.iter().enumerate() {
+            if let Some(second) = chars[i + 1..]
+                .iter()
+                .find(|second| normalization.is_same(&first.to_string(), &second.to_string()))
+            {
+                return Err(GadgetLibError::InvalidShortCode(format!(
+                    "alphabet can't contain both {:?} and {:?}, they are the same in an alias",
+                    first, second
+                )));
+            }
+        }
+
+        Ok(ShortCodes {
+            length,
+            alphabet: chars,
+            host: None,
+        })
+    }
+
+    /// Where the short URLs point, like `https://go.example.com`.
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = Some(host.trim_end_matches('/').to_owned());
+        self
+    }
+
+    pub fn generate(&self) -> String {
+        (0..self.length)
+            .map(|_| self.alphabet[random_index(self.alphabet.len())])
+            .collect()
+    }
+
+    /// The full link for `alias`, when a host is configured.
+    pub fn short_url(&self, alias: &str) -> Option<String> {
+        self.short_url_on(None, alias)
+    }
+
+    /// The full link for `link` on `host` instead of the configured host, keeping its scheme
+    /// and port.
+    pub fn short_url_on(&self, host: Option<&str>, link: &str) -> Option<String> {
+        let base = self.host.as_ref()?;
+        let base = match host {
+            Some(host) => {
+                let mut url = url::Url::parse(base).ok()?;
+                url.set_host(Some(host)).ok()?;
+                url.as_str().trim_end_matches('/').to_owned()
+            }
+            None => base.clone(),
+        };
+        Some(format!("{}/{}", base, link))
+    }
+
+    /// Create a redirect to `destination` with a generated alias, trying another code when
+    /// the generated one is already taken.
+    pub fn create_redirect(
+        &self,
+        backend: &dyn Backend<'_>,
+        namespace: &str,
+        destination: &str,
+        options: &RedirectOptions,
+        username: &str,
+    ) -> LibResult<RedirectModel> {
+        for _ in 0..MAX_ATTEMPTS {
+            let code = self.generate();
+            match backend.create_redirect(namespace, &code, destination, options, username) {
+                Err(GadgetLibError::RedirectExists(_)) => {
+                    debug!("Short code {} is already taken", code);
+                }
+                result => return result,
+            }
+        }
+
+        Err(GadgetLibError::ShortCodesExhausted(MAX_ATTEMPTS))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn random_index(len: usize) -> usize {
+    use rand::{thread_rng, Rng};
+
+    thread_rng().gen_range(0..len)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn random_index(len: usize) -> usize {
+    use rand::{rngs::OsRng, Rng};
+
+    OsRng.gen_range(0..len)
+}
+
+#[test]
+fn generates_codes_from_the_alphabet() {
+    let codes = ShortCodes::new(8, "ab").unwrap();
+    let code = codes.generate();
+    assert_eq!(8, code.len());
+    assert!(code.chars().all(|c| c == 'a' || c == 'b'));
+
+    assert_eq!(None, codes.short_url(&code));
+    assert_eq!(
+        Some("https://go.example.com/abc".to_owned()),
+        codes.with_host("https://go.example.com/").short_url("abc")
+    );
+    assert_eq!(
+        Some("https://go.sre.example.com:8443/abc".to_owned()),
+        ShortCodes::default()
+            .with_host("https://go.example.com:8443")
+            .short_url_on(Some("go.sre.example.com"), "abc")
+    );
+
+    assert!(ShortCodes::new(0, "ab").is_err());
+    assert!(ShortCodes::new(6, "aaa").is_err());
+    assert!(ShortCodes::new(6, "ab/").is_err());
+    assert!(ShortCodes::new(6, "ab~").is_err());
+    assert!(ShortCodes::new(6, "ab-_").is_err());
+    assert!(ShortCodes::new(6, "ab.-").is_err());
+    assert!(ShortCodes::new(6, "abA").is_err());
+    assert!(ShortCodes::new(6, "ab-").is_ok());
+}
+
+#[test]
+fn gives_up_when_codes_are_taken() {
+    use crate::prelude::{InMemoryBackend, DEFAULT_NAMESPACE};
+
+    let backend = InMemoryBackend::new(Vec::new());
+    let codes = ShortCodes::new(1, "ab").unwrap();
+    let created = codes
+        .create_redirect(
+            &backend,
+            DEFAULT_NAMESPACE,
+            "https://example.com/",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+    assert_eq!(1, created.alias.len());
+
+    let other = if created.alias == "a" { "b" } else { "a" };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            other,
+            "https://example.com/",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    assert!(matches!(
+        codes.create_redirect(
+            &backend,
+            DEFAULT_NAMESPACE,
+            "https://example.com/",
+            &Default::default(),
+            "bob"
+        ),
+        Err(GadgetLibError::ShortCodesExhausted(_))
+    ));
+}
@@ -0,0 +1,173 @@
+use crate::api::UserDetails;
+use crate::normalize::AliasNormalization;
+use crate::pattern::AliasPattern;
+use crate::personal;
+use crate::prelude::{Backend, LibResult, RedirectModel};
+use serde::{Deserialize, Serialize};
+
+/// How many suggestions are returned when a caller doesn't ask for a specific amount.
+pub const DEFAULT_SUGGESTION_LIMIT: usize = 10;
+
+/// A suggestion this close is considered "very close", and can be offered as a one-click redirect.
+pub const VERY_CLOSE_DISTANCE: usize = 1;
+
+/// An existing alias that looks like what was asked for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub alias: String,
+    pub destination: String,
+    /// Edit distance between the alias and the query, `0` when the alias starts with the query.
+    pub distance: usize,
+    /// How many times the redirect has been used.
+    pub hits: u64,
+}
+
+impl Suggestion {
+    pub fn is_very_close(&self) -> bool {
+        self.distance <= VERY_CLOSE_DISTANCE
+    }
+}
+
+/// Returns the single very close suggestion, if there is exactly one.
+pub fn single_close_match(suggestions: &[Suggestion]) -> Option<&Suggestion> {
+    let mut close = suggestions.iter().filter(|x| x.is_very_close());
+    match (close.next(), close.next()) {
+        (Some(suggestion), None) => Some(suggestion),
+        _ => None,
+    }
+}
+
+/// Find aliases in `namespace` that are close to the first word of `query`.
+///
+/// Aliases are compared under the backend's normalization. An alias is
+/// close when it starts with the query, or is within a few edits of it. Closer aliases come
+/// first, then the more popular ones. Redirects `user` can't see, and other users' personal
+/// aliases, are never suggested.
+pub fn suggest_aliases<'a>(
+    backend: &dyn Backend<'a>,
+    namespace: &str,
+    query: &str,
+    limit: usize,
+    user: Option<&UserDetails>,
+) -> LibResult<Vec<Suggestion>> {
+    let query = match personal::expand(query, user) {
+        Some(query) => query,
+        None => return Ok(Vec::new()),
+    };
+    let mut redirects = backend.get_all(namespace, 0, usize::MAX)?;
+    redirects.retain(|redirect| {
+        redirect.visible_to(user) && personal::is_listed(&redirect.alias, user, true)
+    });
+    Ok(suggest(&redirects, &query, limit, &backend.normalization()))
+}
+
+pub fn suggest(
+    redirects: &[RedirectModel],
+    query: &str,
+    limit: usize,
+    normalization: &AliasNormalization,
+) -> Vec<Suggestion> {
+    let query = normalization.normalize(query.split(' ').next().unwrap_or_default());
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let max_distance = std::cmp::max(VERY_CLOSE_DISTANCE, query.chars().count() / 3);
+
+    let mut suggestions: Vec<Suggestion> = redirects
+        .iter()
+        .filter(|redirect| redirect.options.pattern == AliasPattern::Exact)
+        .filter_map(|redirect| {
+            let alias = normalization.normalize(&redirect.alias);
+            let distance = if alias.starts_with(&query) {
+                0
+            } else {
+                edit_distance(&alias, &query)
+            };
+
+            if distance > max_distance {
+                return None;
+            }
+
+            Some(Suggestion {
+                alias: redirect.alias.clone(),
+                destination: redirect.destination.clone(),
+                distance,
+                hits: redirect.hits,
+            })
+        })
+        .collect();
+
+    suggestions.sort_by(|a, b| {
+        a.distance
+            .cmp(&b.distance)
+            .then_with(|| b.hits.cmp(&a.hits))
+            .then_with(|| a.alias.cmp(&b.alias))
+    });
+    suggestions.truncate(limit);
+    suggestions
+}
+
+/// Levenshtein distance between two strings, counted in chars.
+fn edit_distance(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+
+    for (i, left_char) in left.chars().enumerate() {
+        let mut current = vec![i + 1; right.len() + 1];
+        for (j, right_char) in right.iter().enumerate() {
+            let substitution = previous[j] + usize::from(left_char != *right_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[right.len()]
+}
+
+#[test]
+fn edit_distance_counts_changes() {
+    assert_eq!(0, edit_distance("docs", "docs"));
+    assert_eq!(1, edit_distance("docs", "doc"));
+    assert_eq!(1, edit_distance("docs", "dics"));
+    assert_eq!(3, edit_distance("kitten", "sitting"));
+}
+
+#[test]
+fn suggests_close_and_popular_aliases() {
+    let options = Default::default();
+    let mut redirects = vec![
+        RedirectModel::new(1, "docs", "https://docs.example.com", &options, None),
+        RedirectModel::new(2, "dogs", "https://dogs.example.com", &options, None),
+        RedirectModel::new(3, "documents", "https://drive.example.com", &options, None),
+        RedirectModel::new(4, "jira", "https://jira.example.com", &options, None),
+    ];
+    redirects[2].hits = 10;
+
+    let normalization = AliasNormalization::default();
+    let suggestions = suggest(&redirects, "doc", 10, &normalization);
+    let aliases: Vec<&str> = suggestions.iter().map(|x| x.alias.as_str()).collect();
+    assert_eq!(vec!["documents", "docs"], aliases);
+    assert!(single_close_match(&suggestions).is_none());
+
+    let suggestions = suggest(&redirects, "dgs", 10, &normalization);
+    assert_eq!(
+        vec!["dogs"],
+        suggestions
+            .iter()
+            .map(|x| x.alias.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    let suggestions = suggest(&redirects, "jra", 10, &normalization);
+    assert_eq!(
+        "jira",
+        single_close_match(&suggestions)
+            .map(|x| x.alias.as_str())
+            .unwrap_or_default()
+    );
+
+    let suggestions = suggest(&redirects, "DOCUMENTS", 10, &normalization);
+    assert_eq!("documents", suggestions[0].alias);
+    assert!(suggest(&redirects, "DOCUMENTS", 10, &AliasNormalization::exact()).is_empty());
+}
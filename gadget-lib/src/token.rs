@@ -0,0 +1,58 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// What a request made with an API token is allowed to do. Each scope includes the ones before it.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// Follow, list, resolve and search redirects.
+    Read,
+    /// Also create, change and delete redirects.
+    Write,
+    /// Also create, list and revoke API tokens.
+    Admin,
+}
+
+impl TokenScope {
+    pub fn allows(&self, needed: TokenScope) -> bool {
+        *self >= needed
+    }
+}
+
+/// An API token as it is stored. Only the hash of the token is kept, the token itself is only
+/// shown when it's created.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ApiTokenModel {
+    pub token_id: String,
+    pub hash: String,
+    /// Requests with the token are made as this user, in these groups.
+    pub username: String,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    pub scope: TokenScope,
+    /// What the token is for.
+    #[serde(default)]
+    pub description: Option<String>,
+    pub created_on: NaiveDateTime,
+    /// When, in UTC, the token stops working.
+    pub expires_at: NaiveDateTime,
+}
+
+impl ApiTokenModel {
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        self.expires_at <= now
+    }
+}
+
+#[test]
+fn scopes_include_the_lesser_ones() {
+    assert!(TokenScope::Admin.allows(TokenScope::Write));
+    assert!(TokenScope::Write.allows(TokenScope::Read));
+    assert!(TokenScope::Write.allows(TokenScope::Write));
+    assert!(!TokenScope::Read.allows(TokenScope::Write));
+    assert!(!TokenScope::Write.allows(TokenScope::Admin));
+    assert_eq!(
+        TokenScope::Read,
+        serde_json::from_str::<TokenScope>(r#""read""#).unwrap()
+    );
+}
@@ -0,0 +1,68 @@
+use crate::api::UserDetails;
+use crate::prelude::RedirectModel;
+use serde::{Deserialize, Serialize};
+
+/// Who can find, list and use a redirect.
+///
+/// To everyone else a restricted redirect doesn't exist, so they get a 404 rather than
+/// learning that there is something there.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Only the listed users, members of the listed groups, and whoever created the redirect.
+    Restricted {
+        #[serde(default)]
+        users: Vec<String>,
+        #[serde(default)]
+        groups: Vec<String>,
+    },
+}
+
+impl Visibility {
+    pub fn allows(&self, user: Option<&UserDetails>, created_by: Option<&str>) -> bool {
+        let (users, groups) = match self {
+            Visibility::Public => return true,
+            Visibility::Restricted { users, groups } => (users, groups),
+        };
+        let user = match user {
+            Some(user) => user,
+            None => return false,
+        };
+
+        created_by == Some(user.username.as_str())
+            || users.contains(&user.username)
+            || user.groups.iter().any(|group| groups.contains(group))
+    }
+}
+
+impl RedirectModel {
+    pub fn visible_to(&self, user: Option<&UserDetails>) -> bool {
+        self.options
+            .visibility
+            .allows(user, self.created_by.as_deref())
+    }
+}
+
+#[test]
+fn restricts_to_users_and_groups() {
+    let visibility: Visibility =
+        serde_json::from_str(r#"{"restricted": {"users": ["alice"], "groups": ["sre"]}}"#).unwrap();
+    let user = |username: &str, groups: &[&str]| UserDetails {
+        username: username.to_owned(),
+        groups: groups.iter().map(|x| x.to_string()).collect(),
+    };
+
+    assert!(visibility.allows(Some(&user("alice", &[])), None));
+    assert!(visibility.allows(Some(&user("bob", &["dev", "sre"])), None));
+    assert!(visibility.allows(Some(&user("carol", &[])), Some("carol")));
+    assert!(!visibility.allows(Some(&user("bob", &["dev"])), Some("carol")));
+    assert!(!visibility.allows(None, None));
+
+    assert!(Visibility::Public.allows(None, None));
+    assert_eq!(
+        Visibility::Public,
+        serde_json::from_str::<Visibility>(r#""public""#).unwrap()
+    );
+}
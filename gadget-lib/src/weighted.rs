@@ -0,0 +1,103 @@
+use crate::prelude::RedirectModel;
+use crate::shortcode::random_index;
+use serde::{Deserialize, Serialize};
+
+/// One of several destinations a redirect splits its traffic between.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct WeightedDestination {
+    pub destination: String,
+    /// Share of the traffic, relative to the other destinations' weights.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// How a request is given one of the weighted destinations.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+    /// Pick by weight on every request.
+    #[default]
+    Random,
+    /// Pick by weight once per user, so a user keeps getting the same destination.
+    /// Anonymous requests are picked at random.
+    Sticky,
+}
+
+/// Picks one of the redirect's weighted destinations by weight, or `None` when it has none.
+/// When every weight is zero, the first one is picked.
+pub fn choose_destination<'a>(
+    redirect: &'a RedirectModel,
+    username: Option<&str>,
+) -> Option<&'a WeightedDestination> {
+    let destinations = &redirect.options.destinations;
+    let total: u64 = destinations.iter().map(|x| x.weight as u64).sum();
+    if total == 0 {
+        return destinations.first();
+    }
+
+    let mut point = match (redirect.options.selection, username) {
+        (Selection::Sticky, Some(username)) => sticky_hash(&redirect.alias, username) % total,
+        _ => random_index(total as usize) as u64,
+    };
+    for destination in destinations {
+        if point < destination.weight as u64 {
+            return Some(destination);
+        }
+        point -= destination.weight as u64;
+    }
+    destinations.last()
+}
+
+/// FNV-1a, so a user keeps their destination across restarts and versions.
+fn sticky_hash(alias: &str, username: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in alias.bytes().chain(Some(0)).chain(username.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+fn split_redirect(selection: Selection, weights: &[u32]) -> RedirectModel {
+    let options = crate::prelude::RedirectOptions {
+        destinations: weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| WeightedDestination {
+                destination: format!("https://mirror-{}.example.com", i),
+                weight: *weight,
+            })
+            .collect(),
+        selection,
+        ..Default::default()
+    };
+    RedirectModel::new(1, "wiki", "https://wiki.example.com", &options, None)
+}
+
+#[test]
+fn picks_by_weight() {
+    let redirect = split_redirect(Selection::Random, &[0, 1, 0]);
+    for _ in 0..20 {
+        assert_eq!(
+            "https://mirror-1.example.com",
+            choose_destination(&redirect, None).unwrap().destination
+        );
+    }
+
+    let redirect = split_redirect(Selection::Random, &[]);
+    assert_eq!(None, choose_destination(&redirect, None));
+}
+
+#[test]
+fn sticks_to_a_user() {
+    let redirect = split_redirect(Selection::Sticky, &[1, 1, 1, 1]);
+    let first = choose_destination(&redirect, Some("bob")).unwrap();
+    for _ in 0..20 {
+        assert_eq!(first, choose_destination(&redirect, Some("bob")).unwrap());
+    }
+}
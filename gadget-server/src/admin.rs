@@ -19,6 +19,12 @@ lazy_static! {
         &["code", "path"]
     )
     .unwrap();
+    static ref REDIRECT_DESTINATION_CHOSEN: CounterVec = register_counter_vec!(
+        "redirect_destination_chosen",
+        "How often each of a redirect's weighted destinations was picked.",
+        &["alias", "destination"]
+    )
+    .unwrap();
     static ref HTTP_REQ_HISTOGRAM: HistogramVec = register_histogram_vec!(
         "http_request_duration_seconds",
         "The HTTP request latencies in seconds.",
@@ -58,6 +64,12 @@ pub fn track_status(info: warp::filters::log::Info) {
         .observe(duration_to_seconds(info.elapsed()));
 }
 
+pub fn track_chosen_destination(alias: &str, destination: &str) {
+    REDIRECT_DESTINATION_CHOSEN
+        .with_label_values(&[alias, destination])
+        .inc();
+}
+
 fn duration_to_seconds(d: std::time::Duration) -> f64 {
     let nanos = f64::from(d.subsec_nanos()) / 1e9;
     d.as_secs() as f64 + nanos
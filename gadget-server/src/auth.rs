@@ -0,0 +1,728 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use gadget_lib::api::UserDetails;
+use gadget_lib::prelude::Backend;
+use gadget_lib::token::{ApiTokenModel, TokenScope};
+use ipnet::IpNet;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, PublicKeyUse};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use tracing::{debug, trace, warn};
+use warp::http::{header::HeaderName, header::AUTHORIZATION, HeaderMap, Method, StatusCode};
+use warp::path::FullPath;
+use warp::Filter;
+
+use crate::handlers::{RequestContext, ResponseMessage};
+use crate::login::Login;
+use crate::tls::{CertUsername, ClientCertificate, ClientConnection};
+
+/// Username of requests that don't say who they are.
+pub const ANONYMOUS_USER: &str = "unknown";
+
+pub const DEFAULT_USERNAME_CLAIM: &str = "sub";
+pub const DEFAULT_GROUPS_CLAIM: &str = "groups";
+
+/// API tokens start with this, so they can be told apart from JWTs.
+pub const TOKEN_PREFIX: &str = "gadget_";
+
+pub const DEFAULT_TRUSTED_PROXIES: &[&str] = &["127.0.0.0/8", "::1/128"];
+pub const DEFAULT_IDENTITY_HEADERS: &[&str] = &["token-claim-sub", "x-amzn-oidc-identity"];
+pub const DEFAULT_GROUP_HEADERS: &[&str] = &["token-claim-groups"];
+
+/// How requests say who they are.
+#[derive(Default)]
+pub struct Auth {
+    jwt: Option<JwtVerifier>,
+    proxy: ProxyHeaders,
+    client_certs: Option<CertUsername>,
+    login: Option<Login>,
+}
+
+impl Auth {
+    pub fn with_jwt(mut self, jwt: JwtVerifier) -> Self {
+        self.jwt = Some(jwt);
+        self
+    }
+
+    pub fn with_proxy_headers(mut self, proxy: ProxyHeaders) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Requests with a verified client certificate are from the user it's for.
+    pub fn with_client_certs(mut self, username: CertUsername) -> Self {
+        self.client_certs = Some(username);
+        self
+    }
+
+    /// Users can sign in with a username and password, and are then known by their session.
+    pub fn with_login(mut self, login: Login) -> Self {
+        self.login = Some(login);
+        self
+    }
+
+    pub fn login(&self) -> Option<&Login> {
+        self.login.as_ref()
+    }
+
+    /// Who a request is from. API tokens are looked up in `tokens`, and `certificate` is the
+    /// client certificate of the connection.
+    pub fn user(
+        &self,
+        method: &Method,
+        headers: &HeaderMap,
+        peer: Option<SocketAddr>,
+        certificate: Option<&ClientCertificate>,
+        tokens: &dyn Backend<'_>,
+    ) -> UserDetails {
+        trace!("Headers: {:?}", headers);
+        if let Some(token) = bearer_token(headers).filter(|x| x.starts_with(TOKEN_PREFIX)) {
+            return match find_token(tokens, token) {
+                Some(token) => UserDetails {
+                    username: token.username,
+                    groups: token.groups,
+                },
+                None => anonymous(),
+            };
+        }
+        if let (Some(username), Some(certificate)) = (self.client_certs, certificate) {
+            match certificate.user(username) {
+                Some(user) => return user,
+                None => warn!(
+                    "The client certificate has no {:?} to use as username",
+                    username
+                ),
+            }
+        }
+        if let Some(user) = self.login.as_ref().and_then(|x| x.user(headers, method)) {
+            return user;
+        }
+        match &self.jwt {
+            Some(jwt) => match bearer_token(headers) {
+                Some(token) => match jwt.verify(token) {
+                    Ok(user) => user,
+                    Err(e) => {
+                        warn!("Ignoring a token that didn't verify: {}", e);
+                        anonymous()
+                    }
+                },
+                None => anonymous(),
+            },
+            // Identity headers can't be checked, so they aren't trusted when certificates or
+            // sessions are.
+            None if self.client_certs.is_some() || self.login.is_some() => anonymous(),
+            None => self.proxy.user(headers, peer),
+        }
+    }
+}
+
+/// Every `interval`, for as long as the server runs, loads the JWT signing keys again so
+/// rotated keys are picked up.
+pub async fn refresh_keys(auth: Arc<Auth>, interval: Duration) {
+    let jwt = match &auth.jwt {
+        Some(jwt) => jwt,
+        None => return,
+    };
+    let mut ticks = tokio::time::interval(interval);
+    // The keys were just loaded, the first tick is right away.
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if let Err(e) = jwt.refresh().await {
+            warn!("Unable to refresh the JWT signing keys: {}", e);
+        }
+    }
+}
+
+pub fn extract_user(
+    context: Arc<RequestContext<'static>>,
+) -> impl Filter<Extract = (UserDetails,), Error = std::convert::Infallible> + Clone {
+    warp::method()
+        .and(warp::filters::header::headers_cloned())
+        .and(warp::addr::remote())
+        .and(warp::ext::optional::<ClientConnection>())
+        .map(
+            move |method: Method,
+                  headers: HeaderMap,
+                  peer: Option<SocketAddr>,
+                  connection: Option<ClientConnection>| {
+                match connection {
+                    Some(connection) => context.user(
+                        &method,
+                        &headers,
+                        Some(connection.peer),
+                        connection.certificate.as_ref(),
+                    ),
+                    None => context.user(&method, &headers, peer, None),
+                }
+            },
+        )
+}
+
+/// Answers requests made with an API token that their token's scope doesn't allow. Every other
+/// request is rejected, so it goes on to the routes after this one.
+pub fn check_token_scope(
+    context: Arc<RequestContext<'static>>,
+) -> impl Filter<Extract = (warp::reply::WithStatus<warp::reply::Json>,), Error = warp::Rejection> + Clone
+{
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::filters::header::headers_cloned())
+        .and_then(move |method: Method, path: FullPath, headers: HeaderMap| {
+            let scope = context.token_scope(&headers);
+            async move {
+                let needed = needed_scope(&method, path.as_str());
+                match scope {
+                    Some(scope) if !scope.allows(needed) => Ok(ResponseMessage::from(format!(
+                        "The API token's scope doesn't allow {:?} access",
+                        needed
+                    ))
+                    .into_raw_response(StatusCode::FORBIDDEN)),
+                    _ => Err(warp::reject::not_found()),
+                }
+            }
+        })
+}
+
+fn needed_scope(method: &Method, path: &str) -> TokenScope {
+    if path.starts_with("/_gadget/api/token") {
+        TokenScope::Admin
+    } else if method == Method::GET
+        || method == Method::HEAD
+        || (method == Method::POST && path == "/_gadget/api/resolve")
+    {
+        TokenScope::Read
+    } else {
+        TokenScope::Write
+    }
+}
+
+/// The unexpired API token a request is made with, if any.
+pub fn api_token(headers: &HeaderMap, tokens: &dyn Backend<'_>) -> Option<ApiTokenModel> {
+    bearer_token(headers)
+        .filter(|token| token.starts_with(TOKEN_PREFIX))
+        .and_then(|token| find_token(tokens, token))
+}
+
+fn find_token(tokens: &dyn Backend<'_>, token: &str) -> Option<ApiTokenModel> {
+    let hash = hash_token(token);
+    let found = match tokens.get_tokens() {
+        Ok(tokens) => tokens.into_iter().find(|token| token.hash == hash),
+        Err(e) => {
+            warn!("Unable to look up API tokens: {}", e);
+            return None;
+        }
+    };
+    match found {
+        Some(token) if token.is_expired(Utc::now().naive_utc()) => {
+            warn!("Ignoring expired API token {}", token.token_id);
+            None
+        }
+        Some(token) => Some(token),
+        None => {
+            warn!("Ignoring an unknown API token");
+            None
+        }
+    }
+}
+
+/// A new API token, and the hash it's stored as.
+pub fn generate_token() -> (String, String) {
+    let token = format!("{}{}", TOKEN_PREFIX, random_hex(32));
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+/// An ID to refer to an API token by, without knowing the token.
+pub fn generate_token_id() -> String {
+    random_hex(8)
+}
+
+fn hash_token(token: &str) -> String {
+    hex(digest(&SHA256, token.as_bytes()).as_ref())
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Unable to generate random bytes");
+    hex(&bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn anonymous() -> UserDetails {
+    UserDetails {
+        username: ANONYMOUS_USER.to_string(),
+        groups: Vec::new(),
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim())
+}
+
+/// The headers an authenticating proxy in front of gadget says who requests are from with, and
+/// where that proxy connects from. The headers of anyone else are ignored.
+#[derive(Debug, Clone)]
+pub struct ProxyHeaders {
+    trusted: Vec<IpNet>,
+    identity_headers: Vec<String>,
+    group_headers: Vec<String>,
+}
+
+impl Default for ProxyHeaders {
+    /// A proxy on the same machine, with the headers of Caddy's JWT plugin and AWS load balancers.
+    fn default() -> Self {
+        ProxyHeaders::new(DEFAULT_TRUSTED_PROXIES.iter().map(|x| x.parse().unwrap()))
+    }
+}
+
+impl ProxyHeaders {
+    pub fn new(trusted: impl IntoIterator<Item = IpNet>) -> Self {
+        ProxyHeaders {
+            trusted: trusted.into_iter().collect(),
+            identity_headers: DEFAULT_IDENTITY_HEADERS
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+            group_headers: DEFAULT_GROUP_HEADERS
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+        }
+    }
+
+    /// Headers with the username, the first one a request has is used.
+    pub fn with_identity_headers(mut self, headers: Vec<String>) -> Self {
+        self.identity_headers = headers;
+        self
+    }
+
+    /// Headers with comma separated groups, the groups from all of them are used.
+    pub fn with_group_headers(mut self, headers: Vec<String>) -> Self {
+        self.group_headers = headers;
+        self
+    }
+
+    fn is_trusted(&self, peer: Option<SocketAddr>) -> bool {
+        match peer {
+            Some(peer) => self.trusted.iter().any(|net| net.contains(&peer.ip())),
+            None => false,
+        }
+    }
+
+    fn user(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> UserDetails {
+        let username = self
+            .identity_headers
+            .iter()
+            .filter_map(|name| headers.get(name.as_str()))
+            .filter_map(|value| value.to_str().ok())
+            .find(|value| !value.is_empty());
+        let username = match username {
+            Some(username) => username,
+            None => return anonymous(),
+        };
+        if !self.is_trusted(peer) {
+            warn!(
+                "Ignoring identity headers from {:?}, which isn't a trusted proxy",
+                peer
+            );
+            return anonymous();
+        }
+
+        UserDetails {
+            username: username.to_string(),
+            groups: self.groups(headers),
+        }
+    }
+
+    /// Groups from the comma separated group headers.
+    fn groups(&self, headers: &HeaderMap) -> Vec<String> {
+        self.group_headers
+            .iter()
+            .flat_map(|name| headers.get_all(name.as_str()))
+            .filter_map(|x| x.to_str().ok())
+            .flat_map(|x| x.split(','))
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect()
+    }
+}
+
+/// A trusted proxy's address, like `10.0.0.5`, or network, like `10.0.0.0/8`.
+pub fn parse_proxy(value: &str) -> Result<IpNet, String> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("{} isn't an IP address or network", value))
+}
+
+/// A header name, in the lower case `HeaderMap` looks them up by.
+pub fn parse_header_name(value: &str) -> Result<String, String> {
+    HeaderName::from_bytes(value.as_bytes())
+        .map(|name| name.as_str().to_owned())
+        .map_err(|_| format!("{} isn't a valid header name", value))
+}
+
+/// Where the keys that sign tokens are published, as a JWKS.
+#[derive(Debug, Clone)]
+pub enum JwksSource {
+    File(PathBuf),
+    Url(String),
+}
+
+impl JwksSource {
+    /// The signing keys. Keys gadget can't use, like encryption keys, are left out.
+    async fn fetch(&self) -> Result<Vec<Jwk>, String> {
+        let json = match self {
+            JwksSource::File(path) => std::fs::read_to_string(path)
+                .map_err(|e| format!("Unable to read {:?}: {}", path, e))?,
+            JwksSource::Url(url) => reqwest::get(url)
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| format!("Unable to fetch {}: {}", url, e))?
+                .text()
+                .await
+                .map_err(|e| format!("Unable to fetch {}: {}", url, e))?,
+        };
+
+        let jwks: Value =
+            serde_json::from_str(&json).map_err(|e| format!("Invalid JWKS: {}", e))?;
+        let keys = match jwks.get("keys") {
+            Some(Value::Array(keys)) => keys,
+            _ => return Err("Invalid JWKS: there is no list of keys".to_owned()),
+        };
+        let keys: Vec<Jwk> = keys
+            .iter()
+            .filter_map(|key| match serde_json::from_value::<Jwk>(key.clone()) {
+                Ok(jwk) if jwk.common.public_key_use != Some(PublicKeyUse::Encryption) => Some(jwk),
+                Ok(_) => None,
+                Err(e) => {
+                    debug!("Skipping a key that can't be used: {}", e);
+                    None
+                }
+            })
+            .collect();
+        if keys.is_empty() {
+            return Err("There are no signing keys in the JWKS".to_owned());
+        }
+        Ok(keys)
+    }
+}
+
+/// Checks signed JWTs from the `Authorization` header, and maps their claims to a user.
+pub struct JwtVerifier {
+    source: JwksSource,
+    keys: RwLock<Vec<Jwk>>,
+    issuer: String,
+    audience: String,
+    username_claim: String,
+    groups_claim: String,
+}
+
+impl JwtVerifier {
+    /// Tokens have to be from `issuer` for `audience`, so ones the same identity provider signed
+    /// for other apps aren't accepted.
+    pub async fn new(source: JwksSource, issuer: &str, audience: &str) -> Result<Self, String> {
+        let keys = source.fetch().await?;
+        Ok(JwtVerifier {
+            source,
+            keys: RwLock::new(keys),
+            issuer: issuer.to_owned(),
+            audience: audience.to_owned(),
+            username_claim: DEFAULT_USERNAME_CLAIM.to_owned(),
+            groups_claim: DEFAULT_GROUPS_CLAIM.to_owned(),
+        })
+    }
+
+    /// The claims the username and groups are taken from. Nested claims are separated by
+    /// `.`, like `realm_access.roles`.
+    pub fn with_claims(mut self, username_claim: &str, groups_claim: &str) -> Self {
+        self.username_claim = username_claim.to_owned();
+        self.groups_claim = groups_claim.to_owned();
+        self
+    }
+
+    pub async fn refresh(&self) -> Result<(), String> {
+        let keys = self.source.fetch().await?;
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// The user the token is for, when it's signed by one of the keys, hasn't expired, and
+    /// is from the issuer for the audience.
+    pub fn verify(&self, token: &str) -> Result<UserDetails, String> {
+        let header = decode_header(token).map_err(|e| e.to_string())?;
+        let key = {
+            let keys = self.keys.read().unwrap();
+            let jwk = match &header.kid {
+                Some(kid) => keys
+                    .iter()
+                    .find(|jwk| jwk.common.key_id.as_ref() == Some(kid)),
+                None if keys.len() == 1 => keys.first(),
+                None => None,
+            }
+            .ok_or_else(|| "None of the keys signed the token".to_owned())?;
+            if !allows_algorithm(jwk, header.alg) {
+                return Err(format!("The key can't be used with {:?}", header.alg));
+            }
+            DecodingKey::from_jwk(jwk).map_err(|e| e.to_string())?
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        let claims = decode::<Value>(token, &key, &validation)
+            .map_err(|e| e.to_string())?
+            .claims;
+
+        let username = match claim(&claims, &self.username_claim) {
+            Some(Value::String(username)) if !username.is_empty() => username.clone(),
+            _ => return Err(format!("The token has no {} claim", self.username_claim)),
+        };
+        let groups = match claim(&claims, &self.groups_claim) {
+            Some(Value::Array(groups)) => groups
+                .iter()
+                .filter_map(|group| group.as_str())
+                .map(|group| group.to_owned())
+                .collect(),
+            Some(Value::String(groups)) => groups
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|group| !group.is_empty())
+                .map(|group| group.to_owned())
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(UserDetails { username, groups })
+    }
+}
+
+/// Whether tokens signed with `algorithm` can be checked with the key. A key that names its
+/// algorithm only allows that one, otherwise any algorithm of the key's type is allowed.
+fn allows_algorithm(jwk: &Jwk, algorithm: Algorithm) -> bool {
+    if let Some(expected) = jwk.common.algorithm {
+        return expected == algorithm;
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => matches!(
+            algorithm,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+        ),
+        AlgorithmParameters::EllipticCurve(_) => {
+            matches!(algorithm, Algorithm::ES256 | Algorithm::ES384)
+        }
+        AlgorithmParameters::OctetKeyPair(_) => algorithm == Algorithm::EdDSA,
+        AlgorithmParameters::OctetKey(_) => matches!(
+            algorithm,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ),
+    }
+}
+
+/// The claim at a `.` separated path.
+fn claim<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(claims, |value, name| value.get(name))
+}
+
+#[tokio::test]
+async fn verifies_signed_tokens() {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::json;
+
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+    let public_key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .unwrap()
+        .public_key()
+        .as_ref()
+        .to_vec();
+    let jwks = json!({"keys": [
+        {"kty": "RSA", "use": "enc", "kid": "other", "n": "AQAB", "e": "AQAB"},
+        {"kty": "OKP", "crv": "Ed25519", "kid": "test", "x": URL_SAFE_NO_PAD.encode(&public_key)}
+    ]});
+    let path = std::env::temp_dir().join(format!("gadget-jwks-{}.json", std::process::id()));
+    std::fs::write(&path, jwks.to_string()).unwrap();
+
+    let verifier = JwtVerifier::new(
+        JwksSource::File(path.clone()),
+        "https://id.example.com",
+        "gadget",
+    )
+    .await
+    .unwrap()
+    .with_claims("email", "realm_access.roles");
+    std::fs::remove_file(&path).unwrap();
+
+    let key = EncodingKey::from_ed_der(pkcs8.as_ref());
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some("test".to_owned());
+    let now = jsonwebtoken::get_current_timestamp();
+    let token = |claims: Value| encode(&header, &claims, &key).unwrap();
+    let claims = json!({
+        "iss": "https://id.example.com",
+        "aud": "gadget",
+        "exp": now + 60,
+        "email": "alice@example.com",
+        "realm_access": {"roles": ["sre", "web"]},
+    });
+
+    let user = verifier.verify(&token(claims.clone())).unwrap();
+    assert_eq!("alice@example.com", user.username);
+    assert_eq!(vec!["sre".to_owned(), "web".to_owned()], user.groups);
+
+    let with = |name: &str, value: Value| {
+        let mut claims = claims.clone();
+        claims[name] = value;
+        token(claims)
+    };
+    assert!(verifier.verify(&with("exp", json!(now - 600))).is_err());
+    assert!(verifier.verify(&with("aud", json!("other"))).is_err());
+    assert!(verifier
+        .verify(&with("iss", json!("https://evil.example.com")))
+        .is_err());
+    assert!(verifier.verify(&with("email", Value::Null)).is_err());
+    let mut without_audience = claims.clone();
+    without_audience.as_object_mut().unwrap().remove("aud");
+    assert!(verifier.verify(&token(without_audience)).is_err());
+
+    let other = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+    let forged = encode(&header, &claims, &EncodingKey::from_ed_der(other.as_ref())).unwrap();
+    assert!(verifier.verify(&forged).is_err());
+    assert!(verifier.verify("not a token").is_err());
+}
+
+#[test]
+fn trusts_identity_headers_only_from_proxies() {
+    let proxy = ProxyHeaders::new(vec![parse_proxy("10.0.0.0/8").unwrap()])
+        .with_identity_headers(vec![parse_header_name("X-Forwarded-User").unwrap()])
+        .with_group_headers(vec![
+            "x-forwarded-groups".to_owned(),
+            "x-forwarded-teams".to_owned(),
+        ]);
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-user", "alice".parse().unwrap());
+    headers.insert("x-forwarded-groups", "sre, web".parse().unwrap());
+    headers.insert("x-forwarded-teams", "oncall".parse().unwrap());
+    headers.insert("token-claim-sub", "mallory".parse().unwrap());
+
+    let user = proxy.user(&headers, Some("10.1.2.3:4000".parse().unwrap()));
+    assert_eq!("alice", user.username);
+    assert_eq!(
+        vec!["sre".to_owned(), "web".to_owned(), "oncall".to_owned()],
+        user.groups
+    );
+
+    let user = proxy.user(&headers, Some("192.168.1.1:4000".parse().unwrap()));
+    assert_eq!(ANONYMOUS_USER, user.username);
+    assert!(user.groups.is_empty());
+    assert_eq!(ANONYMOUS_USER, proxy.user(&headers, None).username);
+
+    assert_eq!(
+        "mallory",
+        ProxyHeaders::default()
+            .user(&headers, Some("[::1]:4000".parse().unwrap()))
+            .username
+    );
+    assert!(parse_proxy("10.0.0.1")
+        .unwrap()
+        .contains(&"10.0.0.1".parse::<IpAddr>().unwrap()));
+    assert!(parse_proxy("proxy.example.com").is_err());
+    assert!(parse_header_name("not a header").is_err());
+}
+
+#[test]
+fn api_tokens_act_as_their_user() {
+    use chrono::Duration;
+    use gadget_lib::prelude::InMemoryBackend;
+
+    let backend = InMemoryBackend::new(Vec::new());
+    let now = Utc::now().naive_utc();
+    let bearer = |scope: TokenScope, expires_at| {
+        let (token, hash) = generate_token();
+        let model = ApiTokenModel {
+            token_id: generate_token_id(),
+            hash,
+            username: "alice".to_owned(),
+            groups: vec!["sre".to_owned()],
+            scope,
+            description: None,
+            created_on: now,
+            expires_at,
+        };
+        backend.create_token(&model).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+        headers
+    };
+    let write = bearer(TokenScope::Write, now + Duration::days(1));
+    let expired = bearer(TokenScope::Admin, now - Duration::days(1));
+    let unknown = {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, "Bearer gadget_0000".parse().unwrap());
+        headers
+    };
+    assert!(backend
+        .get_tokens()
+        .unwrap()
+        .iter()
+        .all(|token| token.hash.len() == 64));
+
+    let auth = Auth::default();
+    let user = auth.user(&Method::POST, &write, None, None, &backend);
+    assert_eq!("alice", user.username);
+    assert_eq!(vec!["sre".to_owned()], user.groups);
+    assert_eq!(
+        Some(TokenScope::Write),
+        api_token(&write, &backend).map(|x| x.scope)
+    );
+    assert_eq!(
+        ANONYMOUS_USER,
+        auth.user(&Method::GET, &expired, None, None, &backend)
+            .username
+    );
+    assert!(api_token(&expired, &backend).is_none());
+    assert_eq!(
+        ANONYMOUS_USER,
+        auth.user(&Method::GET, &unknown, None, None, &backend)
+            .username
+    );
+
+    assert_eq!(TokenScope::Read, needed_scope(&Method::GET, "/docs"));
+    assert_eq!(
+        TokenScope::Read,
+        needed_scope(&Method::POST, "/_gadget/api/resolve")
+    );
+    assert_eq!(
+        TokenScope::Write,
+        needed_scope(&Method::PUT, "/_gadget/api/redirect/docs")
+    );
+    assert_eq!(
+        TokenScope::Admin,
+        needed_scope(&Method::GET, "/_gadget/api/token")
+    );
+}
@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use gadget_lib::api::ResolvedRedirect;
+use reqwest::StatusCode;
+use tracing::{debug, instrument, warn};
+use url::Url;
+
+/// What to do when an upstream can't be reached or gives a bad answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamFailure {
+    /// Log the failure and carry on with the next upstream, as if it didn't have the alias.
+    Skip,
+    /// Stop and report the failure to the caller.
+    Fail,
+}
+
+impl FromStr for UpstreamFailure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(UpstreamFailure::Skip),
+            "fail" => Ok(UpstreamFailure::Fail),
+            _ => Err(format!("Unknown upstream failure handling {}", s)),
+        }
+    }
+}
+
+/// Most answers that are cached at once, so requests for made up aliases can't grow the cache
+/// without end.
+const MAX_CACHED_ANSWERS: usize = 10_000;
+
+struct CachedAnswer {
+    expires: Instant,
+    answer: Option<ResolvedRedirect>,
+}
+
+/// Asks other gadget servers, through their resolve API, for aliases this one doesn't have.
+///
+/// Upstreams are asked in order and the first one to know the alias wins. Answers, including
+/// "not found", are cached for the TTL. Failures are never cached, and neither is an answer
+/// when an upstream before it, or any upstream for "not found", failed.
+pub struct Federation {
+    upstreams: Vec<String>,
+    client: reqwest::Client,
+    ttl: Duration,
+    on_failure: UpstreamFailure,
+    cache: Mutex<HashMap<String, CachedAnswer>>,
+}
+
+impl Federation {
+    pub fn new(
+        upstreams: Vec<String>,
+        ttl: Duration,
+        timeout: Duration,
+        on_failure: UpstreamFailure,
+    ) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Federation {
+            upstreams: upstreams
+                .iter()
+                .map(|x| x.trim_end_matches('/').to_owned())
+                .collect(),
+            client,
+            ttl,
+            on_failure,
+            cache: Default::default(),
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn resolve(&self, query: &str) -> Result<Option<ResolvedRedirect>, String> {
+        if let Some(cached) = self.cache.lock().unwrap().get(query) {
+            if cached.expires > Instant::now() {
+                debug!("Using cached upstream answer for {}", query);
+                return Ok(cached.answer.clone());
+            }
+        }
+
+        let mut answer = None;
+        let mut failed = false;
+        for upstream in &self.upstreams {
+            match self.ask(upstream, query).await {
+                Ok(Some(resolved)) => {
+                    answer = Some(resolved);
+                    break;
+                }
+                Ok(None) => {}
+                Err(e) => match self.on_failure {
+                    UpstreamFailure::Skip => {
+                        warn!("Skipping upstream {}: {}", upstream, e);
+                        failed = true;
+                    }
+                    UpstreamFailure::Fail => return Err(format!("Upstream {}: {}", upstream, e)),
+                },
+            }
+        }
+
+        if !failed {
+            self.cache_answer(query, answer.clone());
+        }
+        Ok(answer)
+    }
+
+    /// Expired answers are dropped first, and nothing more is cached while the cache is full.
+    fn cache_answer(&self, query: &str, answer: Option<ResolvedRedirect>) {
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, cached| cached.expires > now);
+        if cache.len() >= MAX_CACHED_ANSWERS {
+            debug!(
+                "Not caching the upstream answer for {}, the cache is full",
+                query
+            );
+            return;
+        }
+        cache.insert(
+            query.to_owned(),
+            CachedAnswer {
+                expires: now + self.ttl,
+                answer,
+            },
+        );
+    }
+
+    async fn ask(&self, upstream: &str, query: &str) -> Result<Option<ResolvedRedirect>, String> {
+        let url = Url::parse_with_params(
+            &format!("{}/_gadget/api/resolve", upstream),
+            &[("q", query)],
+        )
+        .map_err(|e| e.to_string())?;
+
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match response.status() {
+            StatusCode::OK => Ok(Some(response.json().await.map_err(|e| e.to_string())?)),
+            StatusCode::NOT_FOUND => Ok(None),
+            status => Err(format!("unexpected status {}", status)),
+        }
+    }
+}
+
+#[cfg(test)]
+async fn start_upstream(redirects: &[(&str, &str)]) -> String {
+    use crate::handlers::RequestContext;
+    use gadget_lib::prelude::{Backend, InMemoryBackend, DEFAULT_NAMESPACE};
+    use std::sync::Arc;
+    use warp::Filter;
+
+    let backend = InMemoryBackend::new(Vec::new());
+    for (alias, destination) in redirects {
+        backend
+            .create_redirect(
+                DEFAULT_NAMESPACE,
+                alias,
+                destination,
+                &Default::default(),
+                "test",
+            )
+            .unwrap();
+    }
+    let context = Arc::new(RequestContext::new(Box::new(backend)));
+    let users = context.clone();
+
+    let routes = warp::path!("_gadget" / "api" / "resolve")
+        .and(warp::query::<crate::handlers::ResolveQuery>())
+        .and(warp::header::optional::<String>("host"))
+        .and(crate::auth::extract_user(users))
+        .and(warp::any().map(move || context.clone()))
+        .and_then(crate::handlers::resolve_redirect);
+    let (address, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    format!("http://{}", address)
+}
+
+#[cfg(test)]
+fn unused_upstream() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+#[tokio::test]
+async fn resolves_through_the_first_upstream_that_knows() {
+    let company = start_upstream(&[
+        ("docs", "https://docs.example.com"),
+        ("jira", "https://jira.example.com/{browse/$1}"),
+    ])
+    .await;
+    let team = start_upstream(&[("docs", "https://team.example.com/docs")]).await;
+
+    let federation = Federation::new(
+        vec![team, company],
+        Duration::from_secs(60),
+        Duration::from_secs(5),
+        UpstreamFailure::Fail,
+    )
+    .unwrap();
+
+    let resolved = federation.resolve("docs").await.unwrap().unwrap();
+    assert_eq!("https://team.example.com/docs", resolved.destination);
+
+    let resolved = federation.resolve("jira PROJ-1").await.unwrap().unwrap();
+    assert_eq!("jira", resolved.alias);
+    assert_eq!(
+        "https://jira.example.com/browse/PROJ-1",
+        resolved.destination
+    );
+
+    assert_eq!(None, federation.resolve("missing").await.unwrap());
+}
+
+#[tokio::test]
+async fn caches_upstream_answers() {
+    let upstream = start_upstream(&[("docs", "https://docs.example.com")]).await;
+    let federation = Federation::new(
+        vec![upstream],
+        Duration::from_secs(60),
+        Duration::from_secs(5),
+        UpstreamFailure::Fail,
+    )
+    .unwrap();
+    assert!(federation.resolve("docs").await.unwrap().is_some());
+
+    // Point the only upstream somewhere that doesn't answer, the cached answer is still used.
+    let federation = Federation {
+        upstreams: vec![unused_upstream()],
+        ..federation
+    };
+    assert!(federation.resolve("docs").await.unwrap().is_some());
+    assert!(federation.resolve("other").await.is_err());
+
+    // Expired answers make room for new ones.
+    let upstream = start_upstream(&[("docs", "https://docs.example.com")]).await;
+    let federation = Federation::new(
+        vec![upstream],
+        Duration::from_secs(0),
+        Duration::from_secs(5),
+        UpstreamFailure::Fail,
+    )
+    .unwrap();
+    assert!(federation.resolve("docs").await.unwrap().is_some());
+    assert_eq!(None, federation.resolve("other").await.unwrap());
+    assert_eq!(1, federation.cache.lock().unwrap().len());
+}
+
+#[tokio::test]
+async fn failures_can_be_skipped() {
+    let upstream = start_upstream(&[("docs", "https://docs.example.com")]).await;
+
+    let federation = Federation::new(
+        vec![unused_upstream(), upstream.clone()],
+        Duration::from_secs(60),
+        Duration::from_secs(5),
+        UpstreamFailure::Skip,
+    )
+    .unwrap();
+    assert!(federation.resolve("docs").await.unwrap().is_some());
+
+    // "not found" isn't cached when an upstream failed, it may have the alias once it's back.
+    let federation = Federation {
+        upstreams: vec![unused_upstream()],
+        ..federation
+    };
+    assert_eq!(None, federation.resolve("docs").await.unwrap());
+    let federation = Federation {
+        upstreams: vec![upstream.clone()],
+        ..federation
+    };
+    assert!(federation.resolve("docs").await.unwrap().is_some());
+
+    let federation = Federation::new(
+        vec![unused_upstream(), upstream],
+        Duration::from_secs(60),
+        Duration::from_secs(5),
+        UpstreamFailure::Fail,
+    )
+    .unwrap();
+    assert!(federation.resolve("docs").await.is_err());
+}
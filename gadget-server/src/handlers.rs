@@ -1,22 +1,56 @@
 use gadget_lib::api::*;
 use std::convert::Infallible;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tracing::{debug, error, info, instrument, trace, warn};
+use std::time::Duration;
+use tracing::{debug, error, info, instrument, warn};
 
-use serde::{de::DeserializeOwned, Serialize};
+use chrono::{NaiveDateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use url::Url;
 use warp::{
-    http::header::LOCATION,
-    http::{HeaderMap, HeaderValue, StatusCode},
+    http::header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE, LOCATION, VARY},
+    http::{HeaderMap, Method, StatusCode},
     reply::Reply,
     Filter,
 };
 
-use gadget_lib::prelude::{AliasRedirect, Backend, GadgetLibError, Redirect};
+use gadget_lib::miss::{MissAction, MissPolicy};
+use gadget_lib::namespace::{Namespace, Namespaces, DEFAULT_NAMESPACE};
+use gadget_lib::negotiate::ResponseFormat;
+use gadget_lib::passthrough::apply_passthrough;
+use gadget_lib::personal;
+use gadget_lib::prelude::{
+    resolve_destination, Backend, GadgetLibError, LibResult, RedirectModel, RedirectOptions,
+    RedirectStatus,
+};
+use gadget_lib::resolve::{
+    resolve_batch, resolve_link, DEFAULT_MAX_ALIAS_DEPTH, MAX_RESOLVE_BATCH,
+};
+use gadget_lib::schedule;
+use gadget_lib::shortcode::ShortCodes;
+use gadget_lib::suggest::{suggest_aliases, DEFAULT_SUGGESTION_LIMIT};
+use gadget_lib::token::{ApiTokenModel, TokenScope};
+
+use crate::admin;
+use crate::auth::{self, Auth, ANONYMOUS_USER};
+use crate::federation::Federation;
+use crate::login::Login;
+use crate::pages;
+use crate::sites::{Site, Sites, DEFAULT_BRAND};
+use crate::tls::ClientCertificate;
+use crate::ui::WebDirectory;
 
 #[derive(Clone)]
 pub struct RequestContext<'a> {
     backend: Arc<Box<dyn Backend<'a>>>,
+    max_alias_depth: usize,
+    miss_policy: MissPolicy,
+    federation: Option<Arc<Federation>>,
+    short_codes: ShortCodes,
+    namespaces: Namespaces,
+    sites: Sites<'a>,
+    auth: Arc<Auth>,
 }
 
 unsafe impl std::marker::Send for RequestContext<'_> {}
@@ -27,8 +61,157 @@ impl<'a> RequestContext<'a> {
     pub fn new(backend: Box<dyn Backend<'a>>) -> Self {
         RequestContext {
             backend: Arc::new(backend),
+            max_alias_depth: DEFAULT_MAX_ALIAS_DEPTH,
+            miss_policy: Default::default(),
+            federation: None,
+            short_codes: Default::default(),
+            namespaces: Default::default(),
+            sites: Default::default(),
+            auth: Default::default(),
         }
     }
+
+    pub fn with_max_alias_depth(mut self, max_alias_depth: usize) -> Self {
+        self.max_alias_depth = max_alias_depth;
+        self
+    }
+
+    pub fn with_miss_policy(mut self, miss_policy: MissPolicy) -> Self {
+        self.miss_policy = miss_policy;
+        self
+    }
+
+    pub fn with_federation(mut self, federation: Federation) -> Self {
+        self.federation = Some(Arc::new(federation));
+        self
+    }
+
+    pub fn with_short_codes(mut self, short_codes: ShortCodes) -> Self {
+        self.short_codes = short_codes;
+        self
+    }
+
+    pub fn with_namespaces(mut self, namespaces: Namespaces) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    pub fn with_sites(mut self, sites: Sites<'a>) -> Self {
+        self.sites = sites;
+        self
+    }
+
+    pub fn with_auth(mut self, auth: Arc<Auth>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Who a request is from.
+    pub fn user(
+        &self,
+        method: &Method,
+        headers: &HeaderMap,
+        peer: Option<SocketAddr>,
+        certificate: Option<&ClientCertificate>,
+    ) -> UserDetails {
+        self.auth
+            .user(method, headers, peer, certificate, &**self.backend)
+    }
+
+    /// The scope of the API token a request is made with, if it's made with one.
+    pub fn token_scope(&self, headers: &HeaderMap) -> Option<TokenScope> {
+        auth::api_token(headers, &**self.backend).map(|token| token.scope)
+    }
+
+    /// Where a request for `link` goes. Hosts pinned to a namespace or backend always use it,
+    /// other requests are put in a namespace by its prefix, host or the user's team.
+    fn route(&self, host: &Option<String>, link: &str, user: &UserDetails) -> Route<'_, 'a> {
+        let site = self.sites.find(host.as_deref());
+        let backend: &dyn Backend<'a> = match site.and_then(|site| site.backend.as_ref()) {
+            Some(backend) => &***backend,
+            None => &**self.backend,
+        };
+        let (namespace, link) = match site {
+            Some(site) if site.is_pinned() => (
+                site.config
+                    .namespace
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_NAMESPACE.to_owned()),
+                link.to_owned(),
+            ),
+            _ => self
+                .namespaces
+                .select(host.as_deref(), link, identified(user)),
+        };
+        Route {
+            backend,
+            namespace,
+            link,
+            site,
+        }
+    }
+
+    /// Where anyone can follow `alias` in the route's namespace: through the namespace's prefix,
+    /// or else its first host. There's none for a namespace that only its team is put in.
+    fn short_url(&self, route: &Route<'_, 'a>, alias: &str) -> Option<String> {
+        let site_host = route.site.map(|site| site.config.host.as_str());
+        let namespace = match route.site {
+            Some(site) if site.is_pinned() => None,
+            _ => self.namespaces.get(&route.namespace),
+        };
+        match namespace {
+            Some(Namespace {
+                prefix: Some(prefix),
+                ..
+            }) => self
+                .short_codes
+                .short_url_on(site_host, &format!("{}/{}", prefix, alias)),
+            Some(Namespace { hosts, .. }) if !hosts.is_empty() => {
+                self.short_codes.short_url_on(Some(&hosts[0]), alias)
+            }
+            Some(_) if route.namespace != DEFAULT_NAMESPACE => None,
+            _ => self.short_codes.short_url_on(site_host, alias),
+        }
+    }
+
+    /// Every backend, the server's and the ones hosts have of their own.
+    fn backends(&self) -> Vec<&dyn Backend<'a>> {
+        let mut backends: Vec<&dyn Backend<'a>> = vec![&**self.backend];
+        backends.extend(self.sites.backends().map(|backend| &***backend));
+        backends
+    }
+
+    /// The UI for the host, when it has its own.
+    pub fn ui_directory(&self, host: Option<&str>) -> Option<&WebDirectory> {
+        self.sites.find(host).and_then(|site| site.ui.as_ref())
+    }
+
+    /// The name on gadget's own pages for the host.
+    pub fn brand(&self, host: Option<&str>) -> &str {
+        self.sites
+            .find(host)
+            .map(|site| site.brand())
+            .unwrap_or(DEFAULT_BRAND)
+    }
+
+    pub fn login(&self) -> Option<&Login> {
+        self.auth.login()
+    }
+}
+
+/// The backend and namespace a request is looked up in, and its link without the namespace's
+/// prefix.
+struct Route<'c, 'a> {
+    backend: &'c dyn Backend<'a>,
+    namespace: String,
+    link: String,
+    site: Option<&'c Site<'a>>,
+}
+
+impl Route<'_, '_> {
+    fn brand(&self) -> &str {
+        self.site.map(|site| site.brand()).unwrap_or(DEFAULT_BRAND)
+    }
 }
 
 #[derive(Serialize)]
@@ -73,14 +256,30 @@ pub async fn favicon() -> Result<impl warp::Reply, Infallible> {
 #[instrument(skip(context))]
 pub async fn delete_redirect(
     path: String,
+    host: Option<String>,
+    user: UserDetails,
     context: Arc<RequestContext<'_>>,
 ) -> Result<impl warp::Reply, Infallible> {
-    let resp = context.backend.delete_redirect(&path);
+    if must_sign_in(&user) {
+        return sign_in_response();
+    }
+    let route = context.route(&host, &alias_param(&path), &user);
+    let resp = match writable_redirect(&context, &route, &user) {
+        Ok(Some(redirect)) => route
+            .backend
+            .delete_redirect(&route.namespace, &redirect.public_ref),
+        Ok(None) => Err(GadgetLibError::RedirectDoesNotExists(route.link.clone())),
+        Err(e) => Err(e),
+    };
 
     match resp {
         Err(GadgetLibError::RedirectDoesNotExists(_)) => {
             ResponseMessage::from("not found").into_response(StatusCode::NOT_FOUND)
         }
+        Err(e @ GadgetLibError::PersonalAliasDenied(_))
+        | Err(e @ GadgetLibError::NamespaceAdminOnly(_)) => {
+            ResponseMessage::from(e.to_string()).into_response(StatusCode::FORBIDDEN)
+        }
         Ok(_) => ResponseMessage::from("ok").into_response(StatusCode::OK),
         Err(e) => {
             error!("Unable to update redirect: {:?}", e);
@@ -98,27 +297,63 @@ pub fn json_body<T: DeserializeOwned + Send>(
 #[instrument(skip(context))]
 pub async fn new_redirect_json(
     info: ApiRedirect,
+    host: Option<String>,
     user: UserDetails,
     context: Arc<RequestContext<'_>>,
 ) -> Result<impl warp::Reply, Infallible> {
-    if !is_destination_url(&info.destination) {
-        debug!("Destination wasn't URL {:?}", &info.destination);
-        return ResponseMessage::from(format!("{:?} isn't a valid URL", &info.destination))
+    if let Some(destination) = invalid_destination(&info.destination, Some(&info.options)) {
+        debug!("Destination wasn't URL {:?}", destination);
+        return ResponseMessage::from(format!("{:?} isn't a valid URL", destination))
             .into_response(StatusCode::BAD_REQUEST);
     }
 
-    info!("Creating redirect {} => {}", info.alias, info.destination);
-    match context
-        .backend
-        .create_redirect(&info.alias, &info.destination, &user.username)
-    {
+    if must_sign_in(&user) {
+        return sign_in_response();
+    }
+    let route = context.route(&host, &info.alias, &user);
+    let alias = match writable_alias(&context, &route, &user) {
+        Ok(alias) => alias,
+        Err(e) => return ResponseMessage::from(e.to_string()).into_response(StatusCode::FORBIDDEN),
+    };
+
+    let created = if alias.is_empty() {
+        info!("Creating short code for {}", info.destination);
+        context.short_codes.create_redirect(
+            route.backend,
+            &route.namespace,
+            &info.destination,
+            &info.options,
+            &user.username,
+        )
+    } else {
+        info!("Creating redirect {} => {}", alias, info.destination);
+        route.backend.create_redirect(
+            &route.namespace,
+            &alias,
+            &info.destination,
+            &info.options,
+            &user.username,
+        )
+    };
+
+    match created {
         Ok(result) => {
-            let api_model: ApiRedirect = result.into();
+            let mut api_model: ApiRedirect = result.into();
+            api_model.short_url = context.short_url(&route, &api_model.alias);
             Ok(warp::reply::with_status(
                 warp::reply::json(&api_model),
                 StatusCode::CREATED,
             ))
         }
+        Err(e @ GadgetLibError::AliasLoop(_))
+        | Err(e @ GadgetLibError::InvalidPattern(_))
+        | Err(e @ GadgetLibError::InvalidSchedule(_)) => {
+            ResponseMessage::from(e.to_string()).into_response(StatusCode::BAD_REQUEST)
+        }
+        Err(GadgetLibError::RedirectExists(existing)) => {
+            ResponseMessage::from(format!("Redirect {} already exists", existing))
+                .into_response(StatusCode::CONFLICT)
+        }
         Err(e) => {
             warn!("Unable to create redirect: {:?}", e);
             ResponseMessage::from(format!("Unable to create redirect: {:?}", e))
@@ -131,24 +366,46 @@ pub async fn new_redirect_json(
 pub async fn update_redirect(
     info: String,
     dest: UpdateRedirect,
+    host: Option<String>,
     user: UserDetails,
     context: Arc<RequestContext<'_>>,
 ) -> Result<impl warp::Reply, Infallible> {
-    if !is_destination_url(&dest.destination) {
-        debug!("Destination wasn't URL {:?}", &dest.destination);
-        return ResponseMessage::from(format!("{:?} isn't a valid URL", &dest.destination))
+    if let Some(destination) = invalid_destination(&dest.destination, dest.options.as_ref()) {
+        debug!("Destination wasn't URL {:?}", destination);
+        return ResponseMessage::from(format!("{:?} isn't a valid URL", destination))
             .into_response(StatusCode::BAD_REQUEST);
     }
 
-    let resp = context
-        .backend
-        .update_redirect(&info, &dest.destination, &user.username);
+    if must_sign_in(&user) {
+        return sign_in_response();
+    }
+    let route = context.route(&host, &alias_param(&info), &user);
+    let resp = match writable_redirect(&context, &route, &user) {
+        Ok(Some(redirect)) => route.backend.update_redirect(
+            &route.namespace,
+            &redirect.public_ref,
+            &dest.destination,
+            dest.options.as_ref(),
+            &user.username,
+        ),
+        Ok(None) => Err(GadgetLibError::RedirectDoesNotExists(route.link.clone())),
+        Err(e) => Err(e),
+    };
 
     match resp {
         Ok(_) => ResponseMessage::from("ok").into_response(StatusCode::OK),
         Err(GadgetLibError::RedirectDoesNotExists(_)) => {
             ResponseMessage::from("not found").into_response(StatusCode::NOT_FOUND)
         }
+        Err(e @ GadgetLibError::PersonalAliasDenied(_))
+        | Err(e @ GadgetLibError::NamespaceAdminOnly(_)) => {
+            ResponseMessage::from(e.to_string()).into_response(StatusCode::FORBIDDEN)
+        }
+        Err(e @ GadgetLibError::AliasLoop(_))
+        | Err(e @ GadgetLibError::InvalidPattern(_))
+        | Err(e @ GadgetLibError::InvalidSchedule(_)) => {
+            ResponseMessage::from(e.to_string()).into_response(StatusCode::BAD_REQUEST)
+        }
         Err(e) => {
             error!("Unable to update redirect: {:?}", e);
             ResponseMessage::from(format!("Unexpected error: {:?}", e))
@@ -157,13 +414,37 @@ pub async fn update_redirect(
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ListQuery {
+    /// Include the user's own personal aliases.
+    #[serde(default)]
+    personal: bool,
+    /// List this namespace instead of the one the request is in.
+    namespace: Option<String>,
+}
+
 #[instrument(skip(context))]
 pub async fn list_redirects(
+    query: ListQuery,
+    host: Option<String>,
+    user: UserDetails,
     context: Arc<RequestContext<'_>>,
 ) -> Result<impl warp::Reply, Infallible> {
-    let resp = match context.backend.get_all(0, 10000) {
+    let mut route = context.route(&host, "", &user);
+    if let Some(namespace) = &query.namespace {
+        if !route.site.map(|site| site.is_pinned()).unwrap_or(false) {
+            route.namespace = namespace.clone();
+        }
+    }
+    let user = identified(&user);
+    let resp = match route.backend.get_all(&route.namespace, 0, 10000) {
         Ok(v) => {
-            let data: Vec<ApiRedirect> = v.into_iter().map(|x| x.into()).collect();
+            let data: Vec<ApiRedirect> = v
+                .into_iter()
+                .filter(|x| x.visible_to(user))
+                .filter(|x| personal::is_listed(&x.alias, user, query.personal))
+                .map(|x| x.into())
+                .collect();
             RedirectList { redirects: data }
         }
         Err(GadgetLibError::RedirectDoesNotExists(_)) => RedirectList { redirects: vec![] },
@@ -180,16 +461,438 @@ pub async fn list_redirects(
     ))
 }
 
+#[instrument(skip(context))]
+pub async fn list_history(
+    host: Option<String>,
+    user: UserDetails,
+    context: Arc<RequestContext<'_>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let route = context.route(&host, "", &user);
+    match route.backend.get_history(&route.namespace, 0, 10000) {
+        Ok(mut history) => {
+            history.retain(|entry| entry.redirect.visible_to(identified(&user)));
+            Ok(warp::reply::with_status(
+                warp::reply::json(&HistoryList { history }),
+                StatusCode::OK,
+            ))
+        }
+        Err(e) => {
+            warn!("Unable to get history: {:?}", e);
+            ResponseMessage::from("Unable to get history")
+                .into_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+pub async fn create_token(
+    new_token: NewApiToken,
+    user: UserDetails,
+    context: Arc<RequestContext<'_>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let user = match identified(&user) {
+        Some(user) => user,
+        None => {
+            return ResponseMessage::from("Sign in to create API tokens")
+                .into_response(StatusCode::UNAUTHORIZED)
+        }
+    };
+    let now = Utc::now().naive_utc();
+    if new_token.expires_at <= now {
+        return ResponseMessage::from("The token has to expire in the future")
+            .into_response(StatusCode::BAD_REQUEST);
+    }
+
+    let (token, hash) = auth::generate_token();
+    let model = ApiTokenModel {
+        token_id: auth::generate_token_id(),
+        hash,
+        username: user.username.clone(),
+        groups: user.groups.clone(),
+        scope: new_token.scope,
+        description: new_token.description,
+        created_on: now,
+        expires_at: new_token.expires_at,
+    };
+    match context.backend.create_token(&model) {
+        Ok(_) => {
+            info!(
+                "Created API token {} for {}",
+                model.token_id, model.username
+            );
+            let mut api_token: ApiToken = model.into();
+            api_token.token = Some(token);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&api_token),
+                StatusCode::CREATED,
+            ))
+        }
+        Err(e) => {
+            error!("Unable to create API token: {:?}", e);
+            ResponseMessage::from("Unable to create API token")
+                .into_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// The API tokens of the user.
+pub async fn list_tokens(
+    user: UserDetails,
+    context: Arc<RequestContext<'_>>,
+) -> Result<impl warp::Reply, Infallible> {
+    match user_tokens(&context, &user) {
+        Ok(tokens) => Ok(warp::reply::with_status(
+            warp::reply::json(&ApiTokenList {
+                tokens: tokens.into_iter().map(|token| token.into()).collect(),
+            }),
+            StatusCode::OK,
+        )),
+        Err(e) => {
+            warn!("Unable to get API tokens: {:?}", e);
+            ResponseMessage::from("Unable to get API tokens")
+                .into_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Revokes one of the user's API tokens.
+pub async fn revoke_token(
+    token_id: String,
+    user: UserDetails,
+    context: Arc<RequestContext<'_>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let deleted = user_tokens(&context, &user).and_then(|tokens| {
+        if tokens.iter().any(|token| token.token_id == token_id) {
+            context.backend.delete_token(&token_id)
+        } else {
+            Ok(0)
+        }
+    });
+    match deleted {
+        Ok(0) => ResponseMessage::from("not found").into_response(StatusCode::NOT_FOUND),
+        Ok(_) => {
+            info!("Revoked API token {} of {}", token_id, user.username);
+            ResponseMessage::from("ok").into_response(StatusCode::OK)
+        }
+        Err(e) => {
+            error!("Unable to revoke API token: {:?}", e);
+            ResponseMessage::from("Unable to revoke API token")
+                .into_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn user_tokens(context: &RequestContext<'_>, user: &UserDetails) -> LibResult<Vec<ApiTokenModel>> {
+    let user = match identified(user) {
+        Some(user) => user,
+        None => return Ok(Vec::new()),
+    };
+    let mut tokens = context.backend.get_tokens()?;
+    tokens.retain(|token| token.username == user.username);
+    Ok(tokens)
+}
+
+/// Every `interval`, for as long as the server runs, moves expired redirects into the history
+/// and records the scheduled changes that took over since the last time, in every namespace of
+/// every backend.
+pub async fn sweep_redirects(context: Arc<RequestContext<'_>>, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    let mut since = Utc::now().naive_utc();
+    loop {
+        ticks.tick().await;
+        let now = Utc::now().naive_utc();
+        for backend in context.backends() {
+            sweep_backend(backend, since, now);
+        }
+        since = now;
+    }
+}
+
+fn sweep_backend(backend: &dyn Backend<'_>, since: NaiveDateTime, now: NaiveDateTime) {
+    let namespaces = match backend.namespaces() {
+        Ok(namespaces) => namespaces,
+        Err(e) => {
+            warn!("Unable to list namespaces: {:?}", e);
+            return;
+        }
+    };
+    for namespace in namespaces {
+        match backend.sweep_expired(&namespace, now) {
+            Ok(swept) => {
+                for redirect in swept {
+                    info!("Redirect {} in {} expired", redirect.alias, namespace);
+                }
+            }
+            Err(e) => warn!("Unable to sweep expired redirects: {:?}", e),
+        }
+        match backend.record_scheduled_changes(&namespace, since, now) {
+            Ok(switches) => {
+                for switch in switches {
+                    info!(
+                        "Redirect {} in {} switched: {:?}",
+                        switch.redirect.alias, namespace, switch.event
+                    );
+                }
+            }
+            Err(e) => warn!("Unable to record scheduled changes: {:?}", e),
+        }
+    }
+}
+
+#[instrument(skip(context))]
+pub async fn upcoming_changes(
+    host: Option<String>,
+    user: UserDetails,
+    context: Arc<RequestContext<'_>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let route = context.route(&host, "", &user);
+    match route.backend.get_all(&route.namespace, 0, 10000) {
+        Ok(mut redirects) => {
+            redirects.retain(|redirect| redirect.visible_to(identified(&user)));
+            Ok(warp::reply::with_status(
+                warp::reply::json(&UpcomingChangeList {
+                    changes: schedule::upcoming_changes(&redirects, Utc::now().naive_utc()),
+                }),
+                StatusCode::OK,
+            ))
+        }
+        Err(e) => {
+            warn!("Unable to get redirects: {:?}", e);
+            ResponseMessage::from("Unable to get upcoming changes")
+                .into_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SuggestQuery {
+    q: String,
+    limit: Option<usize>,
+}
+
+#[instrument(skip(context))]
+pub async fn suggest_redirects(
+    query: SuggestQuery,
+    host: Option<String>,
+    user: UserDetails,
+    context: Arc<RequestContext<'_>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let limit = query.limit.unwrap_or(DEFAULT_SUGGESTION_LIMIT);
+    let route = context.route(&host, &query.q, &user);
+    match suggest_aliases(
+        route.backend,
+        &route.namespace,
+        &route.link,
+        limit,
+        identified(&user),
+    ) {
+        Ok(suggestions) => Ok(warp::reply::with_status(
+            warp::reply::json(&SuggestionList {
+                query: query.q,
+                suggestions,
+            }),
+            StatusCode::OK,
+        )),
+        Err(e) => {
+            warn!("Unable to suggest redirects: {:?}", e);
+            ResponseMessage::from("Unable to suggest redirects")
+                .into_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResolveQuery {
+    q: String,
+}
+
+/// Resolves a query against this server's own redirects, without following it.
+#[instrument(skip(context))]
+pub async fn resolve_redirect(
+    query: ResolveQuery,
+    host: Option<String>,
+    user: UserDetails,
+    context: Arc<RequestContext<'_>>,
+) -> Result<impl warp::Reply, Infallible> {
+    let route = context.route(&host, &query.q, &user);
+    match resolve_link(
+        route.backend,
+        &route.namespace,
+        &route.link,
+        context.max_alias_depth,
+        identified(&user),
+    ) {
+        Ok(Some(resolution)) => Ok(warp::reply::with_status(
+            warp::reply::json(&ResolvedRedirect::new(&query.q, resolution)),
+            StatusCode::OK,
+        )),
+        Ok(None) | Err(GadgetLibError::RedirectDoesNotExists(_)) => {
+            ResponseMessage::from("not found").into_response(StatusCode::NOT_FOUND)
+        }
+        Err(e @ GadgetLibError::AliasDepthExceeded(_)) => {
+            ResponseMessage::from(e.to_string()).into_response(StatusCode::LOOP_DETECTED)
+        }
+        Err(e @ GadgetLibError::RedirectExpired(_)) => {
+            ResponseMessage::from(e.to_string()).into_response(StatusCode::GONE)
+        }
+        Err(e) => {
+            warn!("Unable to resolve redirect: {:?}", e);
+            ResponseMessage::from("Unable to resolve redirect")
+                .into_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Resolves many queries at once, each one as [`resolve_redirect`] would.
+#[instrument(skip(context))]
+pub async fn resolve_redirects(
+    batch: ResolveBatch,
+    host: Option<String>,
+    user: UserDetails,
+    context: Arc<RequestContext<'_>>,
+) -> Result<impl warp::Reply, Infallible> {
+    if batch.queries.len() > MAX_RESOLVE_BATCH {
+        return ResponseMessage::from(format!(
+            "At most {} queries can be resolved at once",
+            MAX_RESOLVE_BATCH
+        ))
+        .into_response(StatusCode::BAD_REQUEST);
+    }
+
+    // Each query can be in a different namespace, when it has a namespace's prefix.
+    let results = batch
+        .queries
+        .into_iter()
+        .flat_map(|query| {
+            let route = context.route(&host, &query, &user);
+            let mut results = resolve_batch(
+                route.backend,
+                &route.namespace,
+                vec![route.link],
+                context.max_alias_depth,
+                identified(&user),
+            );
+            for result in &mut results {
+                if let Some(resolved) = result.resolved.as_mut() {
+                    resolved.query = query.clone();
+                }
+                result.query = query.clone();
+            }
+            results
+        })
+        .collect();
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ResolveBatchResult { results }),
+        StatusCode::OK,
+    ))
+}
+
+/// The alias in a redirect API path, where its `/` are encoded, like `~alice%2Fnotes`.
+fn alias_param(alias: &str) -> String {
+    alias.replace("%2F", "/").replace("%2f", "/")
+}
+
+/// Whether `user` has to sign in before changing redirects. Anonymous requests can't change them,
+/// however the server learns who requests are from.
+fn must_sign_in(user: &UserDetails) -> bool {
+    identified(user).is_none()
+}
+
+fn sign_in_response() -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
+    ResponseMessage::from("Sign in to change redirects").into_response(StatusCode::UNAUTHORIZED)
+}
+
+/// The alias `user` means by the route's link, when they are allowed to create it.
+fn writable_alias(
+    context: &RequestContext<'_>,
+    route: &Route<'_, '_>,
+    user: &UserDetails,
+) -> LibResult<String> {
+    let alias = personal::owned_alias(&route.link, identified(user))?;
+    check_writable(context, &route.namespace, &alias, user)?;
+    Ok(alias)
+}
+
+/// The redirect the route's link refers to, by alias or public ref, when `user` is allowed to
+/// change it. Who that is depends on the redirect that was found, not on how it was referred to.
+fn writable_redirect(
+    context: &RequestContext<'_>,
+    route: &Route<'_, '_>,
+    user: &UserDetails,
+) -> LibResult<Option<RedirectModel>> {
+    match visible_redirect(route, &route.link, user)? {
+        Some(redirect) => {
+            check_writable(context, &route.namespace, &redirect.alias, user)?;
+            Ok(Some(redirect))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Personal aliases can only be changed by their owner, every other alias by the namespace's
+/// admins.
+fn check_writable(
+    context: &RequestContext<'_>,
+    namespace: &str,
+    alias: &str,
+    user: &UserDetails,
+) -> LibResult<()> {
+    let user = identified(user);
+    match personal::namespace_owner(alias) {
+        Some(owner) if Some(owner) == user.map(|user| user.username.as_str()) => Ok(()),
+        Some(_) => Err(GadgetLibError::PersonalAliasDenied(alias.to_owned())),
+        None if context.namespaces.is_admin(namespace, user) => Ok(()),
+        None => Err(GadgetLibError::NamespaceAdminOnly(namespace.to_owned())),
+    }
+}
+
+/// The redirect, unless `user` isn't allowed to see it, in which case it doesn't exist for them.
+fn visible_redirect(
+    route: &Route<'_, '_>,
+    redirect_ref: &str,
+    user: &UserDetails,
+) -> LibResult<Option<RedirectModel>> {
+    let redirect_ref = match personal::expand(redirect_ref, identified(user)) {
+        Some(redirect_ref) => redirect_ref,
+        None => return Ok(None),
+    };
+    Ok(route
+        .backend
+        .get_redirect(&route.namespace, &redirect_ref)?
+        .filter(|redirect| redirect.visible_to(identified(user))))
+}
+
 fn is_destination_url(path: &str) -> bool {
     Url::parse(path).is_ok()
 }
 
+/// The first destination, including scheduled and weighted ones, that isn't a URL.
+fn invalid_destination<'a>(
+    destination: &'a str,
+    options: Option<&'a RedirectOptions>,
+) -> Option<&'a str> {
+    let scheduled = options
+        .iter()
+        .flat_map(|options| options.schedule.iter())
+        .map(|change| change.destination.as_str());
+    let weighted = options
+        .iter()
+        .flat_map(|options| options.destinations.iter())
+        .map(|weighted| weighted.destination.as_str());
+    std::iter::once(destination)
+        .chain(scheduled)
+        .chain(weighted)
+        .find(|destination| !is_destination_url(destination))
+}
+
 #[instrument(skip(context))]
 pub async fn get_redirect(
     info: String,
+    host: Option<String>,
+    user: UserDetails,
     context: Arc<RequestContext<'_>>,
 ) -> Result<impl warp::Reply, Infallible> {
-    match context.backend.get_redirect(&info) {
+    let route = context.route(&host, &alias_param(&info), &user);
+    match visible_redirect(&route, &route.link, &user) {
         Ok(Some(value)) => {
             let redirect: ApiRedirect = value.into();
             Ok(warp::reply::with_status(
@@ -208,40 +911,120 @@ pub async fn get_redirect(
     }
 }
 
-#[tracing::instrument(skip(context))]
+#[tracing::instrument(skip(context), fields(chosen))]
 pub async fn find_redirect(
     path: warp::filters::path::Tail,
+    query: String,
+    accept: Option<String>,
+    host: Option<String>,
+    user: UserDetails,
     context: Arc<RequestContext<'_>>,
 ) -> Result<warp::reply::Response, Infallible> {
     let info = path.as_str().replace("%20", " ");
+    let format = ResponseFormat::from_accept(accept.as_deref());
+    let route = context.route(&host, &info, &user);
+    let (link, preview) = preview_request(&route, &query);
+    let query = forwarded_query(&query);
 
-    let redirect_ref: Vec<&str> = info.split(' ').collect();
-    let redirect_ref = match redirect_ref.first() {
-        None => {
-            return Ok(warp::http::Response::builder()
-                .status(StatusCode::TEMPORARY_REDIRECT)
-                .header(LOCATION, "/_gadget/ui")
-                .body(hyper::Body::empty())
-                .unwrap());
-        }
-        Some(value) => value,
-    };
+    if info.is_empty() {
+        return Ok(warp::http::Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header(LOCATION, "/_gadget/ui")
+            .body(hyper::Body::empty())
+            .unwrap());
+    }
 
-    match context.backend.get_redirect(redirect_ref) {
-        Ok(Some(value)) => {
-            let redirect = AliasRedirect::from(value);
-            Ok(warp::http::Response::builder()
-                .status(StatusCode::TEMPORARY_REDIRECT)
-                .header(LOCATION, redirect.get_destination(&info))
-                .body(hyper::Body::empty())
-                .unwrap())
+    match resolve_destination(
+        route.backend,
+        &route.namespace,
+        &link,
+        context.max_alias_depth,
+        identified(&user),
+    ) {
+        Ok(Some(mut resolution)) if preview => {
+            resolution.pass_through(&query, None);
+            let redirect = resolution.redirect.clone();
+            Ok(pages::html_response(
+                StatusCode::OK,
+                pages::preview_page(
+                    route.brand(),
+                    &ResolvedRedirect::new(&info, resolution),
+                    Some(&redirect),
+                ),
+            ))
+        }
+        Ok(Some(mut resolution)) => {
+            // Browsers keep the fragment themselves, when the destination doesn't have one.
+            resolution.pass_through(&query, None);
+            // Every redirect along an `alias:` chain was used, and may have limited uses. Another
+            // request may have taken the last use since the chain was resolved.
+            let now = Utc::now().naive_utc();
+            for redirect in &resolution.followed {
+                match route
+                    .backend
+                    .use_redirect(&route.namespace, &redirect.public_ref, now)
+                {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        return Ok(expired_response(route.brand(), &redirect.alias, format))
+                    }
+                    Err(e) => warn!("Unable to record hit for {}: {:?}", redirect.alias, e),
+                }
+            }
+            if let Some(chosen) = &resolution.chosen {
+                tracing::Span::current().record("chosen", &chosen.as_str());
+                admin::track_chosen_destination(&resolution.redirect.alias, chosen);
+            }
+            Ok(resolved_response(
+                format,
+                &ResolvedRedirect::new(&info, resolution),
+            ))
         }
         Ok(None) | Err(GadgetLibError::RedirectDoesNotExists(_)) => {
-            Ok(warp::http::Response::builder()
-                .status(StatusCode::TEMPORARY_REDIRECT)
-                .header(LOCATION, format!("/_gadget/ui?search={}", &info))
-                .body(hyper::Body::empty())
-                .unwrap())
+            // Upstreams are asked for the link without this server's namespace prefix.
+            if let Some(federation) = &context.federation {
+                match federation.resolve(&link).await {
+                    Ok(Some(mut resolved)) => {
+                        resolved.destination = apply_passthrough(
+                            &resolved.destination,
+                            &query,
+                            None,
+                            Default::default(),
+                        );
+                        if preview {
+                            return Ok(pages::html_response(
+                                StatusCode::OK,
+                                pages::preview_page(route.brand(), &resolved, None),
+                            ));
+                        }
+                        return Ok(resolved_response(format, &resolved));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Unable to ask upstream for {}: {}", link, e);
+                        return ResponseMessage::from("Unable to reach upstream gadget")
+                            .into_response(StatusCode::BAD_GATEWAY)
+                            .map(|x| x.into_response());
+                    }
+                }
+            }
+            Ok(handle_miss(
+                &info,
+                &route,
+                &link,
+                format,
+                identified(&user),
+                &context,
+            ))
+        }
+        Err(GadgetLibError::RedirectExpired(alias)) => {
+            Ok(expired_response(route.brand(), &alias, format))
+        }
+        Err(GadgetLibError::AliasDepthExceeded(alias)) => {
+            warn!("Gave up following alias chain at {}", alias);
+            ResponseMessage::from(format!("Alias chain is too deep at {}", alias))
+                .into_response(StatusCode::LOOP_DETECTED)
+                .map(|x| x.into_response())
         }
         Err(e) => {
             warn!("Unable to get redirect: {:?}", e);
@@ -252,26 +1035,399 @@ pub async fn find_redirect(
     }
 }
 
-fn extract_user_details(value: Option<&'_ HeaderValue>) -> UserDetails {
-    UserDetails {
-        username: value
-            .map(|x| x.to_str().unwrap())
-            .map(|x| x.to_string())
-            .unwrap_or_else(|| "unknown".to_string()),
-    }
+/// The request's query string, empty when there isn't one.
+pub fn raw_query() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::query::raw().or(warp::any().map(String::new)).unify()
 }
 
-pub fn extract_user() -> impl Filter<Extract = (UserDetails,), Error = Infallible> + Clone {
-    warp::filters::header::headers_cloned().map(|headers: HeaderMap| {
-        trace!("Headers: {:?}", headers);
-        if headers.contains_key("token-claim-sub") {
-            extract_user_details(headers.get("token-claim-sub"))
-        } else if headers.contains_key("x-amzn-oidc-identity") {
-            extract_user_details(headers.get("x-amzn-oidc-identity"))
-        } else {
-            UserDetails {
-                username: "unknown".to_string(),
+/// `go/alias+` and `go/alias?preview` show where the alias goes instead of going there.
+///
+/// Returns the query without the `+`, and whether it's a preview. An alias that really
+/// ends with `+` is still redirected to.
+fn preview_request(route: &Route<'_, '_>, query: &str) -> (String, bool) {
+    let info = route.link.clone();
+    let preview = query
+        .split('&')
+        .any(|param| param == "preview" || param.starts_with("preview="));
+
+    let (alias, rest) = match info.split_once(' ') {
+        Some((alias, rest)) => (alias, Some(rest)),
+        None => (info.as_str(), None),
+    };
+    match alias.strip_suffix('+') {
+        Some(stripped) if !stripped.is_empty() => {
+            match route.backend.get_redirect(&route.namespace, alias) {
+                Ok(None) => {
+                    let info = match rest {
+                        Some(rest) => format!("{} {}", stripped, rest),
+                        None => stripped.to_owned(),
+                    };
+                    (info, true)
+                }
+                _ => (info, preview),
             }
         }
+        _ => (info, preview),
+    }
+}
+
+/// The query string to hand on to the destination, without the parameters gadget itself uses.
+fn forwarded_query(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|param| *param != "preview" && !param.starts_with("preview="))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn resolved_response(format: ResponseFormat, resolved: &ResolvedRedirect) -> warp::reply::Response {
+    vary_on_accept(match format {
+        ResponseFormat::Json => {
+            warp::reply::with_status(warp::reply::json(resolved), StatusCode::OK).into_response()
+        }
+        _ => redirect_response(
+            format,
+            &resolved.destination,
+            resolved.status,
+            resolved.cache_control(),
+        ),
+    })
+}
+
+/// Responses that were picked by [`ResponseFormat::from_accept`] say so, so caches keep one of
+/// each format rather than giving one client's format to another.
+fn vary_on_accept(mut response: warp::reply::Response) -> warp::reply::Response {
+    response
+        .headers_mut()
+        .insert(VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+/// A redirect to `destination`, which also names it in the body for [`ResponseFormat::Text`] clients.
+fn redirect_response(
+    format: ResponseFormat,
+    destination: &str,
+    status: RedirectStatus,
+    cache_control: Option<String>,
+) -> warp::reply::Response {
+    let mut builder = warp::http::Response::builder()
+        .status(status.code())
+        .header(LOCATION, destination);
+    if let Some(cache_control) = cache_control {
+        builder = builder.header(CACHE_CONTROL, cache_control);
+    }
+
+    match format {
+        ResponseFormat::Text => builder
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(hyper::Body::from(format!("{}\n", destination)))
+            .unwrap(),
+        _ => builder.body(hyper::Body::empty()).unwrap(),
+    }
+}
+
+fn expired_response(brand: &str, alias: &str, format: ResponseFormat) -> warp::reply::Response {
+    vary_on_accept(match format {
+        ResponseFormat::Redirect => {
+            pages::html_response(StatusCode::GONE, pages::expired_page(brand, alias))
+        }
+        ResponseFormat::Json => ResponseMessage::from(format!("{} has expired", alias))
+            .into_raw_response(StatusCode::GONE)
+            .into_response(),
+        ResponseFormat::Text => text_response(StatusCode::GONE, format!("{} has expired\n", alias)),
     })
 }
+
+fn text_response(status: StatusCode, body: String) -> warp::reply::Response {
+    warp::http::Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(hyper::Body::from(body))
+        .unwrap()
+}
+
+/// The miss policy for a query that nothing matched. The host's wins, then the namespace's,
+/// then the server's.
+fn miss_policy<'c>(route: &Route<'c, '_>, context: &'c RequestContext<'_>) -> &'c MissPolicy {
+    let host_policy = route.site.and_then(|site| site.config.miss_policy.as_ref());
+    let namespace_policy = context
+        .namespaces
+        .get(&route.namespace)
+        .and_then(|namespace| namespace.miss_policy.as_ref());
+    host_policy
+        .or(namespace_policy)
+        .unwrap_or(&context.miss_policy)
+}
+
+/// Answers a query that nothing matched. The miss policy and suggestions get `link`, the query
+/// without the namespace prefix.
+fn handle_miss(
+    info: &str,
+    route: &Route<'_, '_>,
+    link: &str,
+    format: ResponseFormat,
+    user: Option<&UserDetails>,
+    context: &RequestContext<'_>,
+) -> warp::reply::Response {
+    let response = match (miss_policy(route, context).action(link), format) {
+        (MissAction::NotFound, ResponseFormat::Text) => {
+            text_response(StatusCode::NOT_FOUND, "not found\n".to_owned())
+        }
+        (MissAction::NotFound, _) | (MissAction::Redirect(_), ResponseFormat::Json) => {
+            ResponseMessage::from("not found")
+                .into_raw_response(StatusCode::NOT_FOUND)
+                .into_response()
+        }
+        (MissAction::Suggest, format) => {
+            let suggestions = match suggest_aliases(
+                route.backend,
+                &route.namespace,
+                link,
+                DEFAULT_SUGGESTION_LIMIT,
+                user,
+            ) {
+                Ok(suggestions) => suggestions,
+                Err(e) => {
+                    warn!("Unable to suggest redirects: {:?}", e);
+                    Vec::new()
+                }
+            };
+            match format {
+                ResponseFormat::Redirect => pages::html_response(
+                    StatusCode::NOT_FOUND,
+                    pages::suggestions_page(route.brand(), info, &suggestions),
+                ),
+                ResponseFormat::Json => warp::reply::with_status(
+                    warp::reply::json(&SuggestionList {
+                        query: info.to_owned(),
+                        suggestions,
+                    }),
+                    StatusCode::NOT_FOUND,
+                )
+                .into_response(),
+                ResponseFormat::Text => {
+                    let mut body = String::from("not found\n");
+                    for suggestion in suggestions {
+                        body.push_str(&format!(
+                            "did you mean {} => {}\n",
+                            suggestion.alias, suggestion.destination
+                        ));
+                    }
+                    text_response(StatusCode::NOT_FOUND, body)
+                }
+            }
+        }
+        (MissAction::Redirect(destination), format) => {
+            let status = RedirectStatus::default();
+            redirect_response(format, &destination, status, status.cache_control(None))
+        }
+    };
+    vary_on_accept(response)
+}
+
+/// The user, unless the request didn't say who it's from.
+fn identified(user: &UserDetails) -> Option<&UserDetails> {
+    if user.username == ANONYMOUS_USER {
+        None
+    } else {
+        Some(user)
+    }
+}
+
+#[tokio::test]
+async fn counts_uses_along_alias_chains() {
+    use gadget_lib::prelude::InMemoryBackend;
+
+    let backend = InMemoryBackend::new(Default::default());
+    let one_time = RedirectOptions {
+        max_uses: Some(1),
+        ..Default::default()
+    };
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "once",
+            "https://example.com/once",
+            &one_time,
+            "bob",
+        )
+        .unwrap();
+    backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "latest",
+            "alias:once",
+            &Default::default(),
+            "bob",
+        )
+        .unwrap();
+
+    let context = Arc::new(RequestContext::new(Box::new(backend)));
+    let filter = warp::path::tail().and_then(move |tail| {
+        let user = UserDetails {
+            username: ANONYMOUS_USER.to_owned(),
+            groups: Vec::new(),
+        };
+        find_redirect(tail, String::new(), None, None, user, context.clone())
+    });
+
+    let response = warp::test::request().path("/latest").reply(&filter).await;
+    assert_eq!(StatusCode::TEMPORARY_REDIRECT, response.status());
+    assert_eq!("https://example.com/once", response.headers()[LOCATION]);
+    assert_eq!("accept", response.headers()[VARY]);
+
+    let response = warp::test::request().path("/latest").reply(&filter).await;
+    assert_eq!(StatusCode::GONE, response.status());
+    assert_eq!("accept", response.headers()[VARY]);
+
+    let response = warp::test::request().path("/missing").reply(&filter).await;
+    assert_eq!(StatusCode::NOT_FOUND, response.status());
+    assert_eq!("accept", response.headers()[VARY]);
+}
+
+#[tokio::test]
+async fn only_owners_change_personal_links_by_public_ref() {
+    use gadget_lib::prelude::InMemoryBackend;
+
+    let backend = InMemoryBackend::new(Default::default());
+    let notes = backend
+        .create_redirect(
+            DEFAULT_NAMESPACE,
+            "~alice/notes",
+            "https://notes.example.com/alice",
+            &Default::default(),
+            "alice",
+        )
+        .unwrap();
+    let context = Arc::new(RequestContext::new(Box::new(backend)));
+    let user = |username: &str| UserDetails {
+        username: username.to_owned(),
+        groups: Vec::new(),
+    };
+    let update = || UpdateRedirect {
+        destination: "https://evil.example.com".to_owned(),
+        created_by: None,
+        options: None,
+    };
+
+    let updated = update_redirect(
+        notes.public_ref.clone(),
+        update(),
+        None,
+        user(ANONYMOUS_USER),
+        context.clone(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(StatusCode::UNAUTHORIZED, updated.into_response().status());
+    let updated = update_redirect(
+        notes.public_ref.clone(),
+        update(),
+        None,
+        user("bob"),
+        context.clone(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(StatusCode::FORBIDDEN, updated.into_response().status());
+    let deleted = delete_redirect(notes.public_ref.clone(), None, user("bob"), context.clone())
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::FORBIDDEN, deleted.into_response().status());
+    let redirect = context
+        .backend
+        .get_redirect(DEFAULT_NAMESPACE, "~alice/notes")
+        .unwrap()
+        .unwrap();
+    assert_eq!("https://notes.example.com/alice", redirect.destination);
+
+    let updated = update_redirect(
+        notes.public_ref.clone(),
+        update(),
+        None,
+        user("alice"),
+        context.clone(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(StatusCode::OK, updated.into_response().status());
+    let deleted = delete_redirect(notes.public_ref, None, user("alice"), context.clone())
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, deleted.into_response().status());
+}
+
+#[tokio::test]
+async fn team_namespaces_get_links_without_their_prefix() {
+    use gadget_lib::prelude::InMemoryBackend;
+
+    let namespaces = Namespaces::from_json(
+        r#"[{"name": "sre", "prefix": "sre", "miss_policy": "upstream:https://go.sre.example.com"},
+            {"name": "ops", "hosts": ["go.ops.example.com"]},
+            {"name": "dev"}]"#,
+    )
+    .unwrap();
+    let context = Arc::new(
+        RequestContext::new(Box::new(InMemoryBackend::new(Default::default())))
+            .with_namespaces(namespaces)
+            .with_short_codes(ShortCodes::default().with_host("https://go.example.com")),
+    );
+    let user = UserDetails {
+        username: "alice".to_owned(),
+        groups: vec!["dev".to_owned()],
+    };
+
+    let filter = {
+        let context = context.clone();
+        let user = user.clone();
+        warp::path::tail().and_then(move |tail| {
+            find_redirect(
+                tail,
+                String::new(),
+                None,
+                None,
+                user.clone(),
+                context.clone(),
+            )
+        })
+    };
+    let response = warp::test::request()
+        .path("/sre/missing")
+        .reply(&filter)
+        .await;
+    assert_eq!(StatusCode::TEMPORARY_REDIRECT, response.status());
+    assert_eq!(
+        "https://go.sre.example.com/missing",
+        response.headers()[LOCATION]
+    );
+
+    let short_url = |alias: &str, host: Option<&str>| {
+        let info: ApiRedirect = serde_json::from_value(serde_json::json!({
+            "alias": alias,
+            "destination": "https://oncall.example.com",
+        }))
+        .unwrap();
+        let host = host.map(str::to_owned);
+        let (user, context) = (user.clone(), context.clone());
+        async move {
+            let response = new_redirect_json(info, host, user, context)
+                .await
+                .unwrap()
+                .into_response();
+            assert_eq!(StatusCode::CREATED, response.status());
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            serde_json::from_slice::<ApiRedirect>(&body)
+                .unwrap()
+                .short_url
+        }
+    };
+    assert_eq!(
+        Some("https://go.example.com/sre/oncall".to_owned()),
+        short_url("sre/oncall", None).await
+    );
+    assert_eq!(
+        Some("https://go.ops.example.com/oncall".to_owned()),
+        short_url("oncall", Some("go.ops.example.com")).await
+    );
+    assert_eq!(None, short_url("oncall", None).await);
+}
@@ -0,0 +1,578 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::Utc;
+use gadget_lib::api::UserDetails;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use warp::http::header::{COOKIE, LOCATION, SET_COOKIE};
+use warp::http::{HeaderMap, Method, StatusCode};
+
+use crate::handlers::RequestContext;
+use crate::pages;
+
+pub const SESSION_COOKIE: &str = "gadget_session";
+/// Readable by the UI, which sends it back in the [`CSRF_HEADER`]. Before signing in, the sign
+/// in form sends it back instead.
+pub const CSRF_COOKIE: &str = "gadget_csrf";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Checked for users that aren't in the file, so they take as long as a wrong password.
+const DUMMY_HASH: &str = "$2b$12$C6UzMDM.H6dfI/f/IKcEe.5rHgCNQqWvd0XFEmLdlAIW4G6Zq0qEa";
+
+/// Built-in sign in, for running without an authenticating proxy in front of gadget.
+pub struct Login {
+    users: UserFile,
+    sessions: Sessions,
+    secure_cookies: bool,
+}
+
+impl Login {
+    pub fn new(users: UserFile, sessions: Sessions) -> Self {
+        Login {
+            users,
+            sessions,
+            secure_cookies: false,
+        }
+    }
+
+    /// Only send the cookies over HTTPS.
+    pub fn with_secure_cookies(mut self, secure_cookies: bool) -> Self {
+        self.secure_cookies = secure_cookies;
+        self
+    }
+
+    /// The user of the request's session. Requests that change something also need the session's
+    /// CSRF token in the [`CSRF_HEADER`], without it they don't have a user.
+    pub fn user(&self, headers: &HeaderMap, method: &Method) -> Option<UserDetails> {
+        let session = self.sessions.read(cookie(headers, SESSION_COOKIE)?)?;
+        let safe = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+        if !safe {
+            let csrf = headers
+                .get(CSRF_HEADER)
+                .and_then(|x| x.to_str().ok())
+                .filter(|csrf| session.has_csrf(csrf));
+            if csrf.is_none() {
+                warn!(
+                    "Ignoring the session of {} for a {} without its CSRF token",
+                    session.username, method
+                );
+                return None;
+            }
+        }
+        Some(UserDetails {
+            username: session.username,
+            groups: session.groups,
+        })
+    }
+
+    fn set_cookies(&self, cookie_value: &str, session: &Session) -> [String; 2] {
+        let secure = if self.secure_cookies { "; Secure" } else { "" };
+        let max_age = self.sessions.ttl.as_secs();
+        [
+            format!(
+                "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+                SESSION_COOKIE, cookie_value, max_age, secure
+            ),
+            format!(
+                "{}={}; Path=/; Max-Age={}; SameSite=Strict{}",
+                CSRF_COOKIE, session.csrf, max_age, secure
+            ),
+        ]
+    }
+
+    /// The CSRF cookie the sign in form is checked against, before there is a session.
+    fn login_csrf_cookie(&self, csrf: &str) -> String {
+        let secure = if self.secure_cookies { "; Secure" } else { "" };
+        format!(
+            "{}={}; Path=/; SameSite=Strict{}",
+            CSRF_COOKIE, csrf, secure
+        )
+    }
+
+    fn clear_cookies(&self) -> [String; 2] {
+        [
+            format!("{}=; Path=/; Max-Age=0; HttpOnly", SESSION_COOKIE),
+            format!("{}=; Path=/; Max-Age=0", CSRF_COOKIE),
+        ]
+    }
+}
+
+/// Users from an htpasswd style file, with a `username:hash` line for each user, optionally
+/// followed by `:group,group`. Hashes are bcrypt (`$2b$...`) or argon2 (`$argon2id$...`).
+pub struct UserFile {
+    path: PathBuf,
+}
+
+struct LocalUser {
+    username: String,
+    hash: String,
+    groups: Vec<String>,
+}
+
+impl UserFile {
+    pub fn new(path: PathBuf) -> Result<Self, String> {
+        let users = UserFile { path };
+        users.load()?;
+        Ok(users)
+    }
+
+    /// The file is read again for every sign in, so users can be changed without a restart.
+    fn load(&self) -> Result<Vec<LocalUser>, String> {
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("Unable to read {:?}: {}", self.path, e))?;
+        parse_users(&contents).map_err(|e| format!("Invalid user file {:?}: {}", self.path, e))
+    }
+
+    /// The user, when `password` is theirs.
+    pub fn check(&self, username: &str, password: &str) -> Option<UserDetails> {
+        self.check_with(username, password, verify_password)
+    }
+
+    /// Like [`UserFile::check`], with `verify` checking a password against a hash.
+    fn check_with<F>(&self, username: &str, password: &str, verify: F) -> Option<UserDetails>
+    where
+        F: Fn(&str, &str) -> bool,
+    {
+        let users = match self.load() {
+            Ok(users) => users,
+            Err(e) => {
+                warn!("{}", e);
+                return None;
+            }
+        };
+        let user = users.into_iter().find(|user| user.username == username);
+        let hash = user.as_ref().map_or(DUMMY_HASH, |user| user.hash.as_str());
+        if !verify(hash, password) {
+            return None;
+        }
+        user.map(|user| UserDetails {
+            username: user.username,
+            groups: user.groups,
+        })
+    }
+}
+
+fn parse_users(contents: &str) -> Result<Vec<LocalUser>, String> {
+    let mut users = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.splitn(3, ':');
+        let username = parts.next().unwrap_or_default();
+        let hash = parts.next().unwrap_or_default();
+        if username.is_empty() || !(hash.starts_with("$2") || hash.starts_with("$argon2")) {
+            return Err(format!(
+                "line {} isn't `username:hash` with a bcrypt or argon2 hash",
+                number + 1
+            ));
+        }
+        let groups = parts
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_owned())
+            .collect();
+        users.push(LocalUser {
+            username: username.to_owned(),
+            hash: hash.to_owned(),
+            groups,
+        });
+    }
+    Ok(users)
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    if hash.starts_with("$argon2") {
+        match PasswordHash::new(hash) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(_) => false,
+        }
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+/// A signed in user, kept in a signed cookie rather than on the server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Session {
+    id: String,
+    username: String,
+    #[serde(default)]
+    groups: Vec<String>,
+    csrf: String,
+    /// Unix time the session ends at.
+    expires: i64,
+}
+
+impl Session {
+    fn has_csrf(&self, csrf: &str) -> bool {
+        same_token(&self.csrf, csrf)
+    }
+}
+
+/// Signs sessions, and remembers the ones that were signed out of until they would have ended.
+pub struct Sessions {
+    key: hmac::Key,
+    ttl: Duration,
+    revoked: Mutex<HashMap<String, i64>>,
+}
+
+impl Sessions {
+    /// Without a `secret`, a random one is used, and sessions end when the server restarts.
+    pub fn new(secret: Option<&str>, ttl: Duration) -> Self {
+        let key = match secret {
+            Some(secret) => hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            None => hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                .expect("Unable to generate a session key"),
+        };
+        Sessions {
+            key,
+            ttl,
+            revoked: Default::default(),
+        }
+    }
+
+    /// A new session for `user`, and the cookie value that stands for it.
+    fn create(&self, user: &UserDetails) -> (String, Session) {
+        let session = Session {
+            id: random_token(),
+            username: user.username.clone(),
+            groups: user.groups.clone(),
+            csrf: random_token(),
+            expires: Utc::now().timestamp() + self.ttl.as_secs() as i64,
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&session).unwrap());
+        let signature = URL_SAFE_NO_PAD.encode(hmac::sign(&self.key, payload.as_bytes()));
+        (format!("{}.{}", payload, signature), session)
+    }
+
+    /// The session, when the cookie was signed by this server and it hasn't ended.
+    fn read(&self, cookie: &str) -> Option<Session> {
+        let (payload, signature) = cookie.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        hmac::verify(&self.key, payload.as_bytes(), &signature).ok()?;
+        let session: Session =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+
+        if session.expires <= Utc::now().timestamp()
+            || self.revoked.lock().unwrap().contains_key(&session.id)
+        {
+            return None;
+        }
+        Some(session)
+    }
+
+    fn revoke(&self, session: &Session) {
+        let now = Utc::now().timestamp();
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.retain(|_, expires| *expires > now);
+        revoked.insert(session.id.clone(), session.expires);
+    }
+}
+
+fn random_token() -> String {
+    let mut bytes = [0; 24];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("Unable to generate random bytes");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn same_token(expected: &str, given: &str) -> bool {
+    ring::constant_time::verify_slices_are_equal(expected.as_bytes(), given.as_bytes()).is_ok()
+}
+
+fn cookie<'h>(headers: &'h HeaderMap, name: &str) -> Option<&'h str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .flat_map(|x| x.split(';'))
+        .filter_map(|x| x.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Where to go after signing in, only paths on this server. Browsers drop tabs and new lines
+/// from URLs, so `/\t/example.com` would go to another site, and they can't be in a header anyway.
+fn local_path(next: Option<&str>) -> &str {
+    match next {
+        Some(next)
+            if next.starts_with('/')
+                && !next.starts_with("//")
+                && !next.contains('\\')
+                && !next.chars().any(|c| c.is_ascii_control()) =>
+        {
+            next
+        }
+        _ => "/_gadget/ui/",
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+    next: Option<String>,
+    /// Has to match the [`CSRF_COOKIE`], so other sites can't sign anyone in as themselves.
+    csrf: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogoutForm {
+    csrf: String,
+}
+
+/// The sign in form, with a CSRF token it shares with the [`CSRF_COOKIE`]. A token that is
+/// already there is kept, it may belong to a session the UI is using.
+pub async fn login_page(
+    query: LoginQuery,
+    host: Option<String>,
+    headers: HeaderMap,
+    context: Arc<RequestContext<'_>>,
+) -> Result<warp::reply::Response, Infallible> {
+    let login = match context.login() {
+        Some(login) => login,
+        None => return Ok(not_found()),
+    };
+    let csrf = cookie(&headers, CSRF_COOKIE)
+        .filter(|csrf| !csrf.is_empty())
+        .map(|csrf| csrf.to_owned())
+        .unwrap_or_else(random_token);
+    let page = pages::login_page(
+        context.brand(host.as_deref()),
+        local_path(query.next.as_deref()),
+        &csrf,
+        None,
+    );
+    let mut response = pages::html_response(StatusCode::OK, page);
+    if let Ok(value) = login.login_csrf_cookie(&csrf).parse() {
+        response.headers_mut().insert(SET_COOKIE, value);
+    }
+    Ok(response)
+}
+
+pub async fn login(
+    form: LoginForm,
+    host: Option<String>,
+    headers: HeaderMap,
+    context: Arc<RequestContext<'static>>,
+) -> Result<warp::reply::Response, Infallible> {
+    if context.login().is_none() {
+        return Ok(not_found());
+    }
+    let csrf = cookie(&headers, CSRF_COOKIE).unwrap_or_default();
+    if csrf.is_empty() || !same_token(csrf, &form.csrf) {
+        warn!(
+            "Ignoring a sign in of {} without its CSRF token",
+            form.username
+        );
+        return Ok(pages::html_response(
+            StatusCode::FORBIDDEN,
+            "Invalid CSRF token".to_owned(),
+        ));
+    }
+
+    let next = local_path(form.next.as_deref()).to_owned();
+    let checked = {
+        let context = context.clone();
+        let (username, password) = (form.username.clone(), form.password);
+        // Password hashes are slow on purpose, they mustn't hold up other requests.
+        tokio::task::spawn_blocking(move || {
+            context
+                .login()
+                .and_then(|login| login.users.check(&username, &password))
+        })
+        .await
+        .unwrap_or_default()
+    };
+    let login = match context.login() {
+        Some(login) => login,
+        None => return Ok(not_found()),
+    };
+
+    match checked {
+        Some(user) => {
+            info!("{} signed in", user.username);
+            let (cookie_value, session) = login.sessions.create(&user);
+            Ok(see_other(&next, login.set_cookies(&cookie_value, &session)))
+        }
+        None => {
+            warn!("Failed sign in for {}", form.username);
+            let page = pages::login_page(
+                context.brand(host.as_deref()),
+                &next,
+                &form.csrf,
+                Some("The username or password is wrong."),
+            );
+            Ok(pages::html_response(StatusCode::UNAUTHORIZED, page))
+        }
+    }
+}
+
+/// Asks to confirm signing out, so a link can't sign anyone out.
+pub async fn logout_page(
+    host: Option<String>,
+    headers: HeaderMap,
+    context: Arc<RequestContext<'_>>,
+) -> Result<warp::reply::Response, Infallible> {
+    let login = match context.login() {
+        Some(login) => login,
+        None => return Ok(not_found()),
+    };
+    let session = cookie(&headers, SESSION_COOKIE).and_then(|x| login.sessions.read(x));
+    let page = pages::logout_page(
+        context.brand(host.as_deref()),
+        session
+            .as_ref()
+            .map(|x| (x.username.as_str(), x.csrf.as_str())),
+    );
+    Ok(pages::html_response(StatusCode::OK, page))
+}
+
+pub async fn logout(
+    form: LogoutForm,
+    headers: HeaderMap,
+    context: Arc<RequestContext<'_>>,
+) -> Result<warp::reply::Response, Infallible> {
+    let login = match context.login() {
+        Some(login) => login,
+        None => return Ok(not_found()),
+    };
+    if let Some(session) = cookie(&headers, SESSION_COOKIE).and_then(|x| login.sessions.read(x)) {
+        if !session.has_csrf(&form.csrf) {
+            warn!(
+                "Ignoring a sign out of {} without its CSRF token",
+                session.username
+            );
+            return Ok(pages::html_response(
+                StatusCode::FORBIDDEN,
+                "Invalid CSRF token".to_owned(),
+            ));
+        }
+        info!("{} signed out", session.username);
+        login.sessions.revoke(&session);
+    }
+    Ok(see_other("/_gadget/login", login.clear_cookies()))
+}
+
+fn see_other(location: &str, cookies: [String; 2]) -> warp::reply::Response {
+    let mut response = warp::http::Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(LOCATION, location);
+    for cookie in cookies {
+        response = response.header(SET_COOKIE, cookie);
+    }
+    match response.body(hyper::Body::empty()) {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Unable to redirect to {:?}: {}", location, e);
+            pages::html_response(StatusCode::BAD_REQUEST, "Invalid redirect".to_owned())
+        }
+    }
+}
+
+fn not_found() -> warp::reply::Response {
+    pages::html_response(StatusCode::NOT_FOUND, "not found".to_owned())
+}
+
+#[test]
+fn signs_in_with_local_users() {
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    let argon2_hash = Argon2::default()
+        .hash_password(
+            b"hunter2",
+            &SaltString::b64_encode(b"gadget-test-salt").unwrap(),
+        )
+        .unwrap()
+        .to_string();
+    let path = std::env::temp_dir().join(format!("gadget-users-{}", std::process::id()));
+    std::fs::write(
+        &path,
+        format!(
+            "# Local users\nalice:{}:sre,web\nbob:{}\n",
+            bcrypt::hash("correct horse", 4).unwrap(),
+            argon2_hash
+        ),
+    )
+    .unwrap();
+    let users = UserFile::new(path.clone()).unwrap();
+
+    let alice = users.check("alice", "correct horse").unwrap();
+    assert_eq!(vec!["sre".to_owned(), "web".to_owned()], alice.groups);
+    assert!(users.check("alice", "hunter2").is_none());
+    assert_eq!("bob", users.check("bob", "hunter2").unwrap().username);
+    assert!(users.check("mallory", "hunter2").is_none());
+    // Unknown users are still checked, against a hash that takes as long as a real one.
+    let verified = std::cell::RefCell::new(Vec::new());
+    let unknown = users.check_with("mallory", "hunter2", |hash, _| {
+        verified.borrow_mut().push(hash.to_owned());
+        true
+    });
+    assert!(unknown.is_none());
+    assert_eq!(vec![DUMMY_HASH.to_owned()], verified.into_inner());
+    assert!(bcrypt::verify("hunter2", DUMMY_HASH).is_ok());
+
+    std::fs::write(&path, "alice:plaintext\n").unwrap();
+    assert!(users.check("alice", "plaintext").is_none());
+    assert!(UserFile::new(path.clone()).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    let login = Login::new(users, Sessions::new(None, Duration::from_secs(60)));
+    let (cookie_value, session) = login.sessions.create(&alice);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        COOKIE,
+        format!("theme=dark; {}={}", SESSION_COOKIE, cookie_value)
+            .parse()
+            .unwrap(),
+    );
+    assert_eq!(
+        "alice",
+        login.user(&headers, &Method::GET).unwrap().username
+    );
+    assert!(login.user(&headers, &Method::POST).is_none());
+    headers.insert(CSRF_HEADER, "wrong".parse().unwrap());
+    assert!(login.user(&headers, &Method::DELETE).is_none());
+    headers.insert(CSRF_HEADER, session.csrf.parse().unwrap());
+    assert!(login.user(&headers, &Method::DELETE).is_some());
+
+    let tampered = cookie_value.replacen('e', "f", 1);
+    assert!(login.sessions.read(&tampered).is_none());
+    let other_server = Sessions::new(None, Duration::from_secs(60));
+    assert!(other_server.read(&cookie_value).is_none());
+    login.sessions.revoke(&session);
+    assert!(login.user(&headers, &Method::GET).is_none());
+
+    assert_eq!("/docs?x=1", local_path(Some("/docs?x=1")));
+    assert_eq!("/_gadget/ui/", local_path(Some("//evil.example.com")));
+    assert_eq!("/_gadget/ui/", local_path(Some("https://evil.example.com")));
+    assert_eq!("/_gadget/ui/", local_path(Some("/\t/evil.example.com")));
+    assert_eq!("/_gadget/ui/", local_path(Some("/docs\nSet-Cookie: x=1")));
+    assert_eq!(
+        StatusCode::BAD_REQUEST,
+        see_other("/docs\n", login.clear_cookies()).status()
+    );
+}
@@ -1,12 +1,14 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use dotenv::dotenv;
+use futures_util::future::Either;
 use futures_util::join;
 use warp::Filter;
 
 use clap::{clap_app, crate_version};
-use tracing::{error, level_filters::LevelFilter};
+use tracing::{error, level_filters::LevelFilter, warn};
 use tracing_subscriber::{
     fmt::format::{Format, JsonFields},
     layer::SubscriberExt,
@@ -25,7 +27,11 @@ use opentelemetry::{
 };
 use opentelemetry_otlp::WithExportConfig;
 
-use gadget_lib::prelude::create_backend;
+use gadget_lib::miss::MissPolicy;
+use gadget_lib::namespace::Namespaces;
+use gadget_lib::normalize::AliasNormalization;
+use gadget_lib::prelude::{create_backend, Backend, LibResult};
+use gadget_lib::shortcode::{ShortCodes, DEFAULT_SHORT_CODE_ALPHABET};
 
 #[macro_export]
 macro_rules! s {
@@ -35,7 +41,13 @@ macro_rules! s {
 }
 
 mod admin;
+mod auth;
+mod federation;
 mod handlers;
+mod login;
+mod pages;
+mod sites;
+mod tls;
 mod ui;
 
 #[tokio::main]
@@ -56,6 +68,42 @@ async fn main() -> Result<(), &'static str> {
         (@arg listen_metrics: --("listen-metrics") +takes_value default_value("0.0.0.0:8081") "Where should the metrics listen on?")
         (@arg otel_collector: --("opentelemetry-collector") +takes_value env("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT") default_value("http://localhost:4317") "The URL to publish metrics to.")
         (@arg DB_CONNECTION: --("database-url") +required +takes_value env("DATABASE_URL") "URL Database")
+        (@arg alias_case_sensitive: --("alias-case-sensitive") "Treat aliases that only differ by case as different aliases")
+        (@arg alias_distinct_separators: --("alias-distinct-separators") "Treat `-`, `_` and `.` in aliases as different characters")
+        (@arg alias_skip_unicode_normalization: --("alias-skip-unicode-normalization") "Compare aliases without Unicode NFC normalization")
+        (@arg check_alias_collisions: --("check-alias-collisions") "Report existing aliases that collide under the alias normalization, then exit")
+        (@arg max_alias_depth: --("max-alias-depth") +takes_value env("GADGET_MAX_ALIAS_DEPTH") default_value("5") "How many `alias:` destinations to follow before giving up")
+        (@arg miss_policy: --("miss-policy") +takes_value env("GADGET_MISS_POLICY") default_value("suggest") "What to do when nothing matches: 404, suggest, search:<url with %s> or upstream:<url>")
+        (@arg namespaces: --namespaces +takes_value env("GADGET_NAMESPACES") "JSON file of team namespaces, with their hosts, prefix, admins and miss policy")
+        (@arg hosts: --hosts +takes_value env("GADGET_HOSTS") "JSON file of hosts with their own namespace or database, miss policy, brand and UI")
+        (@arg upstream: --upstream +takes_value +multiple number_of_values(1) "Gadget server to ask when an alias isn't found here, tried in order")
+        (@arg upstream_cache_ttl: --("upstream-cache-ttl") +takes_value default_value("300") "How many seconds to keep answers from upstream servers")
+        (@arg upstream_timeout: --("upstream-timeout") +takes_value default_value("2000") "How many milliseconds to wait for an upstream server")
+        (@arg upstream_failure: --("upstream-failure") +takes_value possible_values(&["skip", "fail"]) default_value("skip") "When an upstream fails, skip it or fail the request")
+        (@arg sweep_interval: --("sweep-interval") +takes_value default_value("60") "How many seconds between moving expired redirects into the history, and recording scheduled changes")
+        (@arg short_code_length: --("short-code-length") +takes_value env("GADGET_SHORT_CODE_LENGTH") default_value("6") "How long generated aliases are")
+        (@arg short_code_alphabet: --("short-code-alphabet") +takes_value env("GADGET_SHORT_CODE_ALPHABET") "Characters generated aliases are made from")
+        (@arg short_url_host: --("short-url-host") +takes_value env("GADGET_SHORT_URL_HOST") "Base URL of short links, like https://go.example.com")
+        (@arg trusted_proxy: --("trusted-proxy") +takes_value +multiple number_of_values(1) "Address or network of a proxy whose identity headers are trusted, localhost when there are none")
+        (@arg identity_header: --("identity-header") +takes_value +multiple number_of_values(1) "Header a trusted proxy sets to the username, token-claim-sub and x-amzn-oidc-identity when there are none")
+        (@arg group_header: --("group-header") +takes_value +multiple number_of_values(1) "Header a trusted proxy sets to the user's comma separated groups, token-claim-groups when there are none")
+        (@arg tls_cert: --("tls-cert") +takes_value env("GADGET_TLS_CERT") requires("tls_key") "PEM file with the certificate chain to serve the main listener with TLS")
+        (@arg tls_key: --("tls-key") +takes_value env("GADGET_TLS_KEY") requires("tls_cert") "PEM file with the private key of the TLS certificate")
+        (@arg tls_reload_interval: --("tls-reload-interval") +takes_value default_value("3600") "How many seconds between loading the TLS certificate and key again")
+        (@arg tls_client_ca: --("tls-client-ca") +takes_value env("GADGET_TLS_CLIENT_CA") requires("tls_cert") "PEM file with the CA that client certificates have to be signed by")
+        (@arg tls_client_cert_optional: --("tls-client-cert-optional") requires("tls_client_ca") "Let clients without a certificate in, as anonymous users")
+        (@arg tls_client_username: --("tls-client-username") +takes_value possible_values(&["cn", "email", "dns", "uri"]) default_value("cn") "The part of a client certificate that is the username, the subject's common name or the first email, DNS or URI subject alternative name")
+        (@arg users: --users +takes_value env("GADGET_USERS") "htpasswd style file of users who can sign in, with bcrypt or argon2 password hashes")
+        (@arg session_secret: --("session-secret") +takes_value env("GADGET_SESSION_SECRET") hide_env_values(true) requires("users") "Secret session cookies are signed with, sessions end on restart without one")
+        (@arg session_ttl: --("session-ttl") +takes_value default_value("43200") "How many seconds a sign in lasts")
+        (@arg secure_session_cookie: --("secure-session-cookie") requires("users") "Only send session cookies over HTTPS, always the case with --tls-cert")
+        (@arg jwks_file: --("jwks-file") +takes_value env("GADGET_JWKS_FILE") conflicts_with("jwks_url") "JWKS file with the keys that sign JWTs, which then say who requests are from")
+        (@arg jwks_url: --("jwks-url") +takes_value env("GADGET_JWKS_URL") "URL of the JWKS with the keys that sign JWTs, which then say who requests are from")
+        (@arg jwks_refresh_interval: --("jwks-refresh-interval") +takes_value default_value("3600") "How many seconds between loading the JWKS again")
+        (@arg jwt_issuer: --("jwt-issuer") +takes_value env("GADGET_JWT_ISSUER") "The issuer JWTs have to be from, needed with a JWKS")
+        (@arg jwt_audience: --("jwt-audience") +takes_value env("GADGET_JWT_AUDIENCE") "The audience JWTs have to be for, needed with a JWKS")
+        (@arg jwt_username_claim: --("jwt-username-claim") +takes_value env("GADGET_JWT_USERNAME_CLAIM") default_value(auth::DEFAULT_USERNAME_CLAIM) "The JWT claim with the username, nested claims are separated by `.`")
+        (@arg jwt_groups_claim: --("jwt-groups-claim") +takes_value env("GADGET_JWT_GROUPS_CLAIM") default_value(auth::DEFAULT_GROUPS_CLAIM) "The JWT claim with the user's groups, nested claims are separated by `.`")
     )
     .get_matches();
 
@@ -112,7 +160,13 @@ async fn main() -> Result<(), &'static str> {
         .expect("To have a DB connection")
         .to_string();
 
-    let backend = match create_backend(backend_url) {
+    let normalization = AliasNormalization {
+        case_insensitive: !matches.is_present("alias_case_sensitive"),
+        unify_separators: !matches.is_present("alias_distinct_separators"),
+        unicode_nfc: !matches.is_present("alias_skip_unicode_normalization"),
+    };
+
+    let backend = match create_backend(backend_url, normalization) {
         Ok(backend) => backend,
         Err(e) => {
             error!("{}", e);
@@ -120,10 +174,273 @@ async fn main() -> Result<(), &'static str> {
         }
     };
 
-    let backend = handlers::RequestContext::new(backend);
+    let namespaces = match matches.value_of("namespaces") {
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| Namespaces::from_json(&json).map_err(|e| e.to_string()))
+        {
+            Ok(namespaces) => namespaces,
+            Err(e) => {
+                error!("Unable to load namespaces from {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => Namespaces::default(),
+    };
+
+    let sites = match matches.value_of("hosts") {
+        Some(path) => match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| sites::Sites::from_json(&json, normalization))
+        {
+            Ok(sites) => sites,
+            Err(e) => {
+                error!("Unable to load hosts from {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => sites::Sites::default(),
+    };
+
+    let mut collisions = Vec::new();
+    for backend in std::iter::once(&backend).chain(sites.backends().map(|backend| &**backend)) {
+        match alias_collisions(&**backend) {
+            Ok(found) => collisions.extend(found),
+            Err(e) => {
+                error!("Unable to check for alias collisions: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    for (namespace, collision) in &collisions {
+        warn!(
+            "Aliases {} in {} are the same alias, only the first one will be reachable by alias",
+            collision.join(", "),
+            namespace
+        );
+    }
+    if matches.is_present("check_alias_collisions") {
+        std::process::exit(if collisions.is_empty() { 0 } else { 2 });
+    }
+
+    let max_alias_depth: usize = matches
+        .value_of("max_alias_depth")
+        .expect("To have a max alias depth")
+        .parse()
+        .expect("Unable to parse max-alias-depth");
+
+    let miss_policy: MissPolicy = match matches
+        .value_of("miss_policy")
+        .expect("To have a miss policy")
+        .parse()
+    {
+        Ok(policy) => policy,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let short_code_length: usize = matches
+        .value_of("short_code_length")
+        .expect("To have a short code length")
+        .parse()
+        .expect("Unable to parse short-code-length");
+    let mut short_codes = match ShortCodes::new(
+        short_code_length,
+        matches
+            .value_of("short_code_alphabet")
+            .unwrap_or(DEFAULT_SHORT_CODE_ALPHABET),
+    ) {
+        Ok(short_codes) => short_codes,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(host) = matches.value_of("short_url_host") {
+        short_codes = short_codes.with_host(host);
+    }
+
+    let jwks = match (matches.value_of("jwks_file"), matches.value_of("jwks_url")) {
+        (Some(path), _) => Some(auth::JwksSource::File(path.into())),
+        (None, Some(url)) => Some(auth::JwksSource::Url(url.to_owned())),
+        (None, None) => None,
+    };
+    let mut proxy = match matches.values_of("trusted_proxy") {
+        Some(proxies) => match proxies
+            .map(auth::parse_proxy)
+            .collect::<Result<Vec<_>, _>>()
+        {
+            Ok(proxies) => auth::ProxyHeaders::new(proxies),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => auth::ProxyHeaders::default(),
+    };
+    let header_names = |arg: &str| -> Option<Vec<String>> {
+        match matches
+            .values_of(arg)?
+            .map(auth::parse_header_name)
+            .collect()
+        {
+            Ok(names) => Some(names),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+    if let Some(names) = header_names("identity_header") {
+        proxy = proxy.with_identity_headers(names);
+    }
+    if let Some(names) = header_names("group_header") {
+        proxy = proxy.with_group_headers(names);
+    }
+    let mut auth = auth::Auth::default().with_proxy_headers(proxy);
+    if let Some(jwks) = jwks {
+        // Without them, tokens the same identity provider signed for any other app would do.
+        let (issuer, audience) = match (
+            matches.value_of("jwt_issuer"),
+            matches.value_of("jwt_audience"),
+        ) {
+            (Some(issuer), Some(audience)) => (issuer, audience),
+            _ => {
+                error!("--jwt-issuer and --jwt-audience are needed to check JWTs");
+                std::process::exit(1);
+            }
+        };
+        let mut jwt = match auth::JwtVerifier::new(jwks, issuer, audience).await {
+            Ok(jwt) => jwt,
+            Err(e) => {
+                error!("Unable to load the JWT signing keys: {}", e);
+                std::process::exit(1);
+            }
+        };
+        jwt = jwt.with_claims(
+            matches
+                .value_of("jwt_username_claim")
+                .expect("To have a username claim"),
+            matches
+                .value_of("jwt_groups_claim")
+                .expect("To have a groups claim"),
+        );
+        auth = auth.with_jwt(jwt);
+    }
+
+    if let Some(users) = matches.value_of("users") {
+        let users = match login::UserFile::new(users.into()) {
+            Ok(users) => users,
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        let session_ttl: u64 = matches
+            .value_of("session_ttl")
+            .expect("To have a session ttl")
+            .parse()
+            .expect("Unable to parse session-ttl");
+        let secret = matches.value_of("session_secret");
+        if secret.is_none() {
+            warn!("There is no --session-secret, everyone will be signed out when the server restarts");
+        }
+        let sessions = login::Sessions::new(secret, Duration::from_secs(session_ttl.max(1)));
+        auth = auth.with_login(login::Login::new(users, sessions).with_secure_cookies(
+            matches.is_present("secure_session_cookie") || matches.is_present("tls_cert"),
+        ));
+    }
+
+    let tls = match (matches.value_of("tls_cert"), matches.value_of("tls_key")) {
+        (Some(cert_path), Some(key_path)) => {
+            let settings = tls::TlsSettings {
+                cert_path: cert_path.into(),
+                key_path: key_path.into(),
+                client_ca_path: matches.value_of("tls_client_ca").map(|x| x.into()),
+                client_cert_optional: matches.is_present("tls_client_cert_optional"),
+            };
+            match tls::TlsListener::new(&settings) {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    error!("Unable to set up TLS: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+    if matches.is_present("tls_client_ca") {
+        let username = matches
+            .value_of("tls_client_username")
+            .expect("To have a client certificate username")
+            .parse()
+            .expect("Unable to parse tls-client-username");
+        auth = auth.with_client_certs(username);
+    }
+    let auth = Arc::new(auth);
+
+    let mut backend = handlers::RequestContext::new(backend)
+        .with_max_alias_depth(max_alias_depth)
+        .with_miss_policy(miss_policy)
+        .with_short_codes(short_codes)
+        .with_namespaces(namespaces)
+        .with_sites(sites)
+        .with_auth(auth.clone());
+
+    if let Some(upstreams) = matches.values_of("upstream") {
+        let ttl: u64 = matches
+            .value_of("upstream_cache_ttl")
+            .expect("To have an upstream cache ttl")
+            .parse()
+            .expect("Unable to parse upstream-cache-ttl");
+        let timeout: u64 = matches
+            .value_of("upstream_timeout")
+            .expect("To have an upstream timeout")
+            .parse()
+            .expect("Unable to parse upstream-timeout");
+        let on_failure = matches
+            .value_of("upstream_failure")
+            .expect("To have upstream failure handling")
+            .parse()
+            .expect("Unable to parse upstream-failure");
+
+        let federation = match federation::Federation::new(
+            upstreams.map(|x| x.to_owned()).collect(),
+            Duration::from_secs(ttl),
+            Duration::from_millis(timeout),
+            on_failure,
+        ) {
+            Ok(federation) => federation,
+            Err(e) => {
+                error!("Unable to create upstream client: {}", e);
+                std::process::exit(1);
+            }
+        };
+        backend = backend.with_federation(federation);
+    }
 
     let backend = Arc::new(backend);
 
+    let sweep_interval: u64 = matches
+        .value_of("sweep_interval")
+        .expect("To have a sweep interval")
+        .parse()
+        .expect("Unable to parse sweep-interval");
+    let sweeper =
+        handlers::sweep_redirects(backend.clone(), Duration::from_secs(sweep_interval.max(1)));
+
+    let jwks_refresh_interval: u64 = matches
+        .value_of("jwks_refresh_interval")
+        .expect("To have a JWKS refresh interval")
+        .parse()
+        .expect("Unable to parse jwks-refresh-interval");
+    let key_refresher = auth::refresh_keys(
+        auth.clone(),
+        Duration::from_secs(jwks_refresh_interval.max(1)),
+    );
+
     let ui_root_dir = matches.value_of("ui_directory").expect("To have UI Path");
     let web_dir = match ui::WebDirectory::new(ui_root_dir.to_string()) {
         Some(x) => x,
@@ -131,40 +448,132 @@ async fn main() -> Result<(), &'static str> {
     };
     let web_dir = Arc::new(web_dir);
 
-    let main_server = warp::path!("favicon.ico")
-        .and_then(handlers::favicon)
+    let main_server = auth::check_token_scope(backend.clone())
+        .or(warp::path!("favicon.ico").and_then(handlers::favicon))
         .or(warp::path!("_gadget" / "api" / "redirect")
             .and(warp::get())
+            .and(warp::query::<handlers::ListQuery>())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
             .and(with_context(backend.clone()))
             .and_then(handlers::list_redirects))
         .or(warp::path!("_gadget" / "api" / "redirect")
             .and(warp::post())
             .and(handlers::json_body())
-            .and(handlers::extract_user())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
             .and(with_context(backend.clone()))
             .and_then(handlers::new_redirect_json))
         .or(warp::path!("_gadget" / "api" / "redirect" / String)
             .and(warp::get())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
             .and(with_context(backend.clone()))
             .and_then(handlers::get_redirect))
         .or(warp::path!("_gadget" / "api" / "redirect" / String)
             .and(warp::delete())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
             .and(with_context(backend.clone()))
             .and_then(handlers::delete_redirect))
         .or(warp::path!("_gadget" / "api" / "redirect" / String)
             .and(warp::put())
             .and(handlers::json_body())
-            .and(handlers::extract_user())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
             .and(with_context(backend.clone()))
             .and_then(handlers::update_redirect))
+        .or(warp::path!("_gadget" / "api" / "token")
+            .and(warp::get())
+            .and(auth::extract_user(backend.clone()))
+            .and(with_context(backend.clone()))
+            .and_then(handlers::list_tokens))
+        .or(warp::path!("_gadget" / "api" / "token")
+            .and(warp::post())
+            .and(handlers::json_body())
+            .and(auth::extract_user(backend.clone()))
+            .and(with_context(backend.clone()))
+            .and_then(handlers::create_token))
+        .or(warp::path!("_gadget" / "api" / "token" / String)
+            .and(warp::delete())
+            .and(auth::extract_user(backend.clone()))
+            .and(with_context(backend.clone()))
+            .and_then(handlers::revoke_token))
+        .or(warp::path!("_gadget" / "api" / "schedule")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
+            .and(with_context(backend.clone()))
+            .and_then(handlers::upcoming_changes))
+        .or(warp::path!("_gadget" / "api" / "history")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
+            .and(with_context(backend.clone()))
+            .and_then(handlers::list_history))
+        .or(warp::path!("_gadget" / "api" / "resolve")
+            .and(warp::get())
+            .and(warp::query::<handlers::ResolveQuery>())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
+            .and(with_context(backend.clone()))
+            .and_then(handlers::resolve_redirect))
+        .or(warp::path!("_gadget" / "api" / "resolve")
+            .and(warp::post())
+            .and(handlers::json_body())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
+            .and(with_context(backend.clone()))
+            .and_then(handlers::resolve_redirects))
+        .or(warp::path!("_gadget" / "api" / "suggest")
+            .and(warp::get())
+            .and(warp::query::<handlers::SuggestQuery>())
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
+            .and(with_context(backend.clone()))
+            .and_then(handlers::suggest_redirects))
+        .or(warp::path!("_gadget" / "login")
+            .and(warp::get())
+            .and(warp::query::<login::LoginQuery>())
+            .and(warp::header::optional::<String>("host"))
+            .and(warp::header::headers_cloned())
+            .and(with_context(backend.clone()))
+            .and_then(login::login_page))
+        .or(warp::path!("_gadget" / "login")
+            .and(warp::post())
+            .and(warp::body::content_length_limit(16 * 1024))
+            .and(warp::body::form())
+            .and(warp::header::optional::<String>("host"))
+            .and(warp::header::headers_cloned())
+            .and(with_context(backend.clone()))
+            .and_then(login::login))
+        .or(warp::path!("_gadget" / "logout")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("host"))
+            .and(warp::header::headers_cloned())
+            .and(with_context(backend.clone()))
+            .and_then(login::logout_page))
+        .or(warp::path!("_gadget" / "logout")
+            .and(warp::post())
+            .and(warp::body::content_length_limit(16 * 1024))
+            .and(warp::body::form())
+            .and(warp::header::headers_cloned())
+            .and(with_context(backend.clone()))
+            .and_then(login::logout))
         .or(warp::path("_gadget")
             .and(warp::path("ui"))
             .and(warp::path::tail())
             .and(warp::get())
+            .and(warp::header::optional::<String>("host"))
             .and(with_web_dir(web_dir))
+            .and(with_context(backend.clone()))
             .and_then(ui::serve_embedded))
         .or(warp::get()
             .and(warp::path::tail())
+            .and(handlers::raw_query())
+            .and(warp::header::optional::<String>("accept"))
+            .and(warp::header::optional::<String>("host"))
+            .and(auth::extract_user(backend.clone()))
             .and(with_context(backend.clone()))
             .and_then(handlers::find_redirect))
         .or(warp::any().map(|| {
@@ -180,7 +589,21 @@ async fn main() -> Result<(), &'static str> {
         .parse()
         .expect("Unable to parse listen_server");
 
-    let main_server = warp::serve(main_server).run(listen_server);
+    let tls_reload_interval: u64 = matches
+        .value_of("tls_reload_interval")
+        .expect("To have a TLS reload interval")
+        .parse()
+        .expect("Unable to parse tls-reload-interval");
+    let (main_server, cert_reloader) = match &tls {
+        Some(tls) => (
+            Either::Left(tls.serve(main_server, listen_server)),
+            Either::Left(tls.reload_certs(Duration::from_secs(tls_reload_interval.max(1)))),
+        ),
+        None => (
+            Either::Right(warp::serve(main_server).run(listen_server)),
+            Either::Right(futures_util::future::ready(())),
+        ),
+    };
 
     let admin_server = warp::path("metrics")
         .map(admin::metrics_endpoint)
@@ -197,11 +620,28 @@ async fn main() -> Result<(), &'static str> {
 
     let admin_server = warp::serve(admin_server).run(listen_metrics);
 
-    let (_main, _admin) = join!(main_server, admin_server);
+    let (_main, _admin, _sweeper, _key_refresher, _cert_reloader) = join!(
+        main_server,
+        admin_server,
+        sweeper,
+        key_refresher,
+        cert_reloader
+    );
 
     Ok(())
 }
 
+/// Aliases in each namespace of `backend` that collide under its alias normalization.
+fn alias_collisions(backend: &dyn Backend<'_>) -> LibResult<Vec<(String, Vec<String>)>> {
+    let mut collisions = Vec::new();
+    for namespace in backend.namespaces()? {
+        for collision in backend.alias_collisions(&namespace)? {
+            collisions.push((namespace.clone(), collision));
+        }
+    }
+    Ok(collisions)
+}
+
 fn with_context(
     context: Arc<handlers::RequestContext>,
 ) -> impl Filter<Extract = (Arc<handlers::RequestContext>,), Error = std::convert::Infallible> + Clone
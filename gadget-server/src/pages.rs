@@ -0,0 +1,235 @@
+use gadget_lib::api::ResolvedRedirect;
+use gadget_lib::prelude::RedirectModel;
+use gadget_lib::suggest::{single_close_match, Suggestion};
+use warp::http::{header::CONTENT_TYPE, StatusCode};
+
+pub fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn html_response(status: StatusCode, body: String) -> warp::reply::Response {
+    warp::http::Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(hyper::Body::from(body))
+        .unwrap()
+}
+
+/// A page with `brand` as the name in its header and title.
+fn page(brand: &str, title: &str, content: &str) -> String {
+    format!(
+        r#"<!doctype html>
+<html lang="en">
+
+<head>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1, shrink-to-fit=no" />
+    <title>{title} - {brand}</title>
+    <link type="text/css" rel="stylesheet" href="https://unpkg.com/bootstrap/dist/css/bootstrap.min.css" />
+</head>
+
+<body>
+    <header>
+        <div class="navbar navbar-dark bg-dark shadow-sm">
+            <div class="container d-flex justify-content-between">
+                <strong style="color:white;">{brand}</strong>
+            </div>
+        </div>
+    </header>
+
+    <main class="container mt-4">
+{content}
+    </main>
+</body>
+
+</html>
+"#,
+        brand = escape_html(brand),
+        title = escape_html(title),
+        content = content
+    )
+}
+
+/// Link to an alias, keeping the arguments from the original query.
+fn alias_link(alias: &str, query: &str) -> String {
+    let arguments: Vec<&str> = query.split(' ').skip(1).collect();
+    let mut link = format!("/{}", alias);
+    if !arguments.is_empty() {
+        link = format!("{}%20{}", link, urlencoding::encode(&arguments.join(" ")));
+    }
+    link
+}
+
+/// The page shown when nothing matched `query`.
+pub fn suggestions_page(brand: &str, query: &str, suggestions: &[Suggestion]) -> String {
+    let alias = query.split(' ').next().unwrap_or_default();
+    let mut content = format!(
+        "        <h1 class=\"h3\">There is no redirect for <code>{}</code></h1>\n",
+        escape_html(alias)
+    );
+
+    if let Some(suggestion) = single_close_match(suggestions) {
+        content.push_str(&format!(
+            "        <p class=\"lead\">Did you mean <a class=\"btn btn-primary\" href=\"{}\">{}</a>?</p>\n",
+            escape_html(&alias_link(&suggestion.alias, query)),
+            escape_html(&suggestion.alias)
+        ));
+    }
+
+    if suggestions.is_empty() {
+        content.push_str("        <p>Nothing looks close to it either.</p>\n");
+    } else {
+        content.push_str("        <p>These look close:</p>\n        <ul class=\"list-group\">\n");
+        for suggestion in suggestions {
+            content.push_str(&format!(
+                "            <li class=\"list-group-item\"><a href=\"{}\">{}</a> <small class=\"text-muted\">{}</small></li>\n",
+                escape_html(&alias_link(&suggestion.alias, query)),
+                escape_html(&suggestion.alias),
+                escape_html(&suggestion.destination)
+            ));
+        }
+        content.push_str("        </ul>\n");
+    }
+
+    page(brand, &format!("No redirect for {}", alias), &content)
+}
+
+/// The page shown instead of the destination once a redirect has expired.
+pub fn expired_page(brand: &str, alias: &str) -> String {
+    let content = format!(
+        "        <h1 class=\"h3\">The link <code>{}</code> has expired</h1>\n        <p>It was only available for a limited time, or a limited number of uses.</p>\n",
+        escape_html(alias)
+    );
+
+    page(brand, &format!("{} has expired", alias), &content)
+}
+
+/// Shows where a query goes, and who is behind the redirect, instead of going there.
+///
+/// `redirect` is missing when the answer came from an upstream server.
+pub fn preview_page(
+    brand: &str,
+    resolved: &ResolvedRedirect,
+    redirect: Option<&RedirectModel>,
+) -> String {
+    let unknown = || "unknown".to_owned();
+    let mut details = vec![
+        ("Alias", resolved.alias.clone()),
+        ("Destination", resolved.destination.clone()),
+    ];
+    match redirect {
+        Some(redirect) => {
+            details.push((
+                "Description",
+                redirect
+                    .options
+                    .description
+                    .clone()
+                    .unwrap_or_else(|| "none".to_owned()),
+            ));
+            details.push(("Owner", redirect.created_by.clone().unwrap_or_else(unknown)));
+            details.push((
+                "Last edited by",
+                redirect
+                    .last_edited_by
+                    .clone()
+                    .or_else(|| redirect.created_by.clone())
+                    .unwrap_or_else(unknown),
+            ));
+            details.push(("Clicks", redirect.hits.to_string()));
+        }
+        None => details.push(("Owner", "an upstream gadget".to_owned())),
+    }
+
+    let mut content = format!(
+        "        <h1 class=\"h3\">Where <code>{}</code> goes</h1>\n        <dl class=\"row\">\n",
+        escape_html(&resolved.query)
+    );
+    for (name, value) in details {
+        content.push_str(&format!(
+            "            <dt class=\"col-sm-3\">{}</dt><dd class=\"col-sm-9\">{}</dd>\n",
+            name,
+            escape_html(&value)
+        ));
+    }
+    content.push_str("        </dl>\n");
+
+    for warning in &resolved.warnings {
+        content.push_str(&format!(
+            "        <div class=\"alert alert-warning\">{}</div>\n",
+            escape_html(warning)
+        ));
+    }
+
+    // Only offer to follow links that a browser would treat as a page, not `javascript:` and friends.
+    let followable = url::Url::parse(&resolved.destination)
+        .map(|url| url.scheme() == "http" || url.scheme() == "https")
+        .unwrap_or(false);
+    if followable {
+        content.push_str(&format!(
+            "        <a class=\"btn btn-primary\" rel=\"noreferrer\" href=\"{}\">Continue</a>\n",
+            escape_html(&resolved.destination)
+        ));
+    } else {
+        content.push_str(
+            "        <div class=\"alert alert-danger\">This destination isn't a web page, so it can't be followed from here.</div>\n",
+        );
+    }
+
+    page(brand, &format!("Preview of {}", resolved.alias), &content)
+}
+
+/// The form to sign in with, which goes on to `next` once signed in.
+pub fn login_page(brand: &str, next: &str, csrf: &str, error: Option<&str>) -> String {
+    let mut content = String::from("        <h1 class=\"h3\">Sign in</h1>\n");
+    if let Some(error) = error {
+        content.push_str(&format!(
+            "        <div class=\"alert alert-danger\">{}</div>\n",
+            escape_html(error)
+        ));
+    }
+    content.push_str(&format!(
+        r#"        <form method="post" action="/_gadget/login">
+            <input type="hidden" name="next" value="{}" />
+            <input type="hidden" name="csrf" value="{}" />
+            <div class="form-group"><label for="username">Username</label><input class="form-control" id="username" name="username" autocomplete="username" required autofocus /></div>
+            <div class="form-group"><label for="password">Password</label><input class="form-control" id="password" name="password" type="password" autocomplete="current-password" required /></div>
+            <button class="btn btn-primary" type="submit">Sign in</button>
+        </form>
+"#,
+        escape_html(next),
+        escape_html(csrf)
+    ));
+
+    page(brand, "Sign in", &content)
+}
+
+/// Asks to confirm signing out. `session` is the signed in user and their session's CSRF token.
+pub fn logout_page(brand: &str, session: Option<(&str, &str)>) -> String {
+    let content = match session {
+        Some((username, csrf)) => format!(
+            r#"        <h1 class="h3">Sign out of <code>{}</code>?</h1>
+        <form method="post" action="/_gadget/logout">
+            <input type="hidden" name="csrf" value="{}" />
+            <button class="btn btn-primary" type="submit">Sign out</button>
+        </form>
+"#,
+            escape_html(username),
+            escape_html(csrf)
+        ),
+        None => "        <h1 class=\"h3\">You aren't signed in</h1>\n        <a class=\"btn btn-primary\" href=\"/_gadget/login\">Sign in</a>\n".to_owned(),
+    };
+
+    page(brand, "Sign out", &content)
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use gadget_lib::miss::MissPolicy;
+use gadget_lib::namespace::is_same_host;
+use gadget_lib::normalize::AliasNormalization;
+use gadget_lib::prelude::{create_backend, Backend};
+use serde::Deserialize;
+
+use crate::ui::WebDirectory;
+
+/// The name on gadget's own pages, for hosts without a brand.
+pub const DEFAULT_BRAND: &str = "Gadget";
+
+/// How requests to one of the hosts gadget answers for are served.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SiteConfig {
+    pub host: String,
+    /// Every link on the host is in this namespace, whatever its prefix or the user's team.
+    pub namespace: Option<String>,
+    /// The host has its own backend, instead of the server's `--database-url`.
+    pub database_url: Option<String>,
+    /// What happens when nothing matches, instead of the namespace's or the server's miss policy.
+    pub miss_policy: Option<MissPolicy>,
+    /// The name shown on gadget's own pages.
+    pub brand: Option<String>,
+    /// Where the host's UI is served from, instead of `--ui-path`.
+    pub ui_path: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Site<'a> {
+    pub config: SiteConfig,
+    pub backend: Option<Arc<Box<dyn Backend<'a>>>>,
+    pub ui: Option<WebDirectory>,
+}
+
+impl<'a> Site<'a> {
+    pub fn new(config: SiteConfig, normalization: AliasNormalization) -> Result<Self, String> {
+        let backend = match &config.database_url {
+            Some(url) => Some(Arc::new(
+                create_backend(url.clone(), normalization).map_err(|e| e.to_string())?,
+            )),
+            None => None,
+        };
+        let ui = match &config.ui_path {
+            Some(path) => Some(
+                WebDirectory::new(path.clone())
+                    .ok_or_else(|| format!("Unable to access UI directory {}", path))?,
+            ),
+            None => None,
+        };
+
+        Ok(Site {
+            config,
+            backend,
+            ui,
+        })
+    }
+
+    /// Whether the host's links are kept apart, so a prefix or team can't reach other namespaces.
+    pub fn is_pinned(&self) -> bool {
+        self.config.namespace.is_some() || self.backend.is_some()
+    }
+
+    pub fn brand(&self) -> &str {
+        self.config.brand.as_deref().unwrap_or(DEFAULT_BRAND)
+    }
+}
+
+/// Every host with its own settings. Requests to other hosts use the server's.
+#[derive(Clone, Default)]
+pub struct Sites<'a> {
+    sites: Vec<Site<'a>>,
+}
+
+impl<'a> Sites<'a> {
+    pub fn new(sites: Vec<Site<'a>>) -> Result<Self, String> {
+        for (i, site) in sites.iter().enumerate() {
+            if sites[..i]
+                .iter()
+                .any(|other| is_same_host(&other.config.host, &site.config.host))
+            {
+                return Err(format!("{} is configured twice", site.config.host));
+            }
+        }
+        Ok(Sites { sites })
+    }
+
+    /// Sites from a JSON list, like `[{"host": "gto.cx", "namespace": "public", "brand": "gto.cx"}]`.
+    pub fn from_json(json: &str, normalization: AliasNormalization) -> Result<Self, String> {
+        let configs: Vec<SiteConfig> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let sites = configs
+            .into_iter()
+            .map(|config| Site::new(config, normalization))
+            .collect::<Result<Vec<_>, _>>()?;
+        Sites::new(sites)
+    }
+
+    /// The site for a request's `Host` header.
+    pub fn find(&self, host: Option<&str>) -> Option<&Site<'a>> {
+        let host = host?;
+        self.sites
+            .iter()
+            .find(|site| is_same_host(&site.config.host, host))
+    }
+
+    /// The backends of the sites that have their own.
+    pub fn backends(&self) -> impl Iterator<Item = &Arc<Box<dyn Backend<'a>>>> {
+        self.sites.iter().filter_map(|site| site.backend.as_ref())
+    }
+}
+
+#[test]
+fn finds_sites_by_host() {
+    let sites = Sites::from_json(
+        r#"[
+            {"host": "gto.cx", "database_url": "memory://", "brand": "gto.cx", "miss_policy": "404"},
+            {"host": "go", "namespace": "internal"},
+            {"host": "go.example.com", "brand": "Example"}
+        ]"#,
+        AliasNormalization::default(),
+    )
+    .unwrap();
+
+    let public = sites.find(Some("GTO.cx:443")).unwrap();
+    assert!(public.is_pinned());
+    assert_eq!("gto.cx", public.brand());
+    assert_eq!(Some(MissPolicy::NotFound), public.config.miss_policy);
+    assert!(sites.find(Some("go")).unwrap().is_pinned());
+    assert!(!sites.find(Some("go.example.com")).unwrap().is_pinned());
+    assert!(sites.find(Some("other")).is_none());
+    assert!(sites.find(None).is_none());
+    assert_eq!(1, sites.backends().count());
+
+    assert!(Sites::from_json(
+        r#"[{"host": "go"}, {"host": "GO"}]"#,
+        AliasNormalization::default()
+    )
+    .is_err());
+}
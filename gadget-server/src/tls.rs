@@ -0,0 +1,340 @@
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use gadget_lib::api::UserDetails;
+use hyper::server::conn::Http;
+use hyper::service::{service_fn, Service};
+use rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello,
+    ResolvesServerCert,
+};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+use warp::{Filter, Reply};
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Which part of a client certificate is the username. The groups are always the subject's
+/// organizational units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertUsername {
+    /// The subject's common name.
+    CommonName,
+    /// The first email address in the subject alternative names.
+    Email,
+    /// The first DNS name in the subject alternative names.
+    Dns,
+    /// The first URI in the subject alternative names.
+    Uri,
+}
+
+impl std::str::FromStr for CertUsername {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "cn" => Ok(CertUsername::CommonName),
+            "email" => Ok(CertUsername::Email),
+            "dns" => Ok(CertUsername::Dns),
+            "uri" => Ok(CertUsername::Uri),
+            _ => Err(format!(
+                "{} isn't a client certificate field, expected cn, email, dns or uri",
+                value
+            )),
+        }
+    }
+}
+
+/// The parts of a verified client certificate a user can be taken from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientCertificate {
+    pub common_name: Option<String>,
+    pub organizational_units: Vec<String>,
+    pub emails: Vec<String>,
+    pub dns_names: Vec<String>,
+    pub uris: Vec<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &[u8]) -> Result<Self, String> {
+        let (_, cert) = X509Certificate::from_der(der).map_err(|e| e.to_string())?;
+        let subject = cert.subject();
+        let mut client = ClientCertificate {
+            common_name: subject
+                .iter_common_name()
+                .find_map(|x| x.as_str().ok())
+                .map(|x| x.to_owned()),
+            organizational_units: subject
+                .iter_organizational_unit()
+                .filter_map(|x| x.as_str().ok())
+                .map(|x| x.to_owned())
+                .collect(),
+            ..Default::default()
+        };
+
+        if let Ok(Some(names)) = cert.subject_alternative_name() {
+            for name in &names.value.general_names {
+                match name {
+                    GeneralName::RFC822Name(email) => client.emails.push(email.to_string()),
+                    GeneralName::DNSName(dns) => client.dns_names.push(dns.to_string()),
+                    GeneralName::URI(uri) => client.uris.push(uri.to_string()),
+                    _ => {}
+                }
+            }
+        }
+        Ok(client)
+    }
+
+    /// The user the certificate is for, when it has the field the username is taken from.
+    pub fn user(&self, username: CertUsername) -> Option<UserDetails> {
+        let name = match username {
+            CertUsername::CommonName => self.common_name.as_ref(),
+            CertUsername::Email => self.emails.first(),
+            CertUsername::Dns => self.dns_names.first(),
+            CertUsername::Uri => self.uris.first(),
+        }?;
+        Some(UserDetails {
+            username: name.clone(),
+            groups: self.organizational_units.clone(),
+        })
+    }
+}
+
+/// Who is on the other end of a TLS connection, added to each of its requests.
+#[derive(Debug, Clone)]
+pub struct ClientConnection {
+    pub peer: SocketAddr,
+    pub certificate: Option<ClientCertificate>,
+}
+
+pub struct TlsSettings {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Clients have to have a certificate signed by this CA.
+    pub client_ca_path: Option<PathBuf>,
+    /// Clients without a certificate are let in, as anonymous users.
+    pub client_cert_optional: bool,
+}
+
+/// The server's certificate and key, which can be loaded again from their paths.
+struct ReloadingCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCert {
+    fn new(cert_path: PathBuf, key_path: PathBuf) -> Result<Self, String> {
+        let key = load_certified_key(&cert_path, &key_path)?;
+        Ok(ReloadingCert {
+            cert_path,
+            key_path,
+            key: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    fn reload(&self) -> Result<(), String> {
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.key.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
+
+/// Terminates TLS for the main listener.
+pub struct TlsListener {
+    acceptor: TlsAcceptor,
+    cert: Arc<ReloadingCert>,
+}
+
+impl TlsListener {
+    pub fn new(settings: &TlsSettings) -> Result<Self, String> {
+        let cert = Arc::new(ReloadingCert::new(
+            settings.cert_path.clone(),
+            settings.key_path.clone(),
+        )?);
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &settings.client_ca_path {
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for ca in load_certs(ca_path)? {
+                    roots
+                        .add(&ca)
+                        .map_err(|e| format!("Invalid CA in {:?}: {}", ca_path, e))?;
+                }
+                let verifier = if settings.client_cert_optional {
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+                } else {
+                    AllowAnyAuthenticatedClient::new(roots)
+                };
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_cert_resolver(cert.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsListener {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            cert,
+        })
+    }
+
+    /// Every `interval`, for as long as the server runs, loads the certificate and key again so
+    /// renewed certificates are picked up.
+    pub async fn reload_certs(&self, interval: Duration) {
+        let mut ticks = tokio::time::interval(interval);
+        // The certificate was just loaded, the first tick is right away.
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            match self.cert.reload() {
+                Ok(_) => debug!("Reloaded TLS certificate"),
+                Err(e) => warn!("Unable to reload the TLS certificate: {}", e),
+            }
+        }
+    }
+
+    /// Serves `filter` over TLS on `address`. Each request has the [`ClientConnection`] it
+    /// came in on.
+    pub async fn serve<F>(&self, filter: F, address: SocketAddr)
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: Reply,
+    {
+        let listener = match TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("Unable to listen on {}: {}", address, e);
+                return;
+            }
+        };
+        info!("Listening with TLS on {}", address);
+
+        let service = warp::service(filter);
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Unable to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let acceptor = self.acceptor.clone();
+            let service = service.clone();
+            tokio::spawn(async move {
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!("TLS handshake with {} failed: {}", peer, e);
+                        return;
+                    }
+                };
+                let certificate = stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .and_then(|cert| match ClientCertificate::from_der(&cert.0) {
+                        Ok(cert) => Some(cert),
+                        Err(e) => {
+                            warn!("Unable to read the client certificate of {}: {}", peer, e);
+                            None
+                        }
+                    });
+                let connection = ClientConnection { peer, certificate };
+
+                let service = service_fn(move |mut request| {
+                    request.extensions_mut().insert(connection.clone());
+                    service.clone().call(request)
+                });
+                if let Err(e) = Http::new().serve_connection(stream, service).await {
+                    debug!("Connection with {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, String> {
+    let file =
+        std::fs::File::open(path).map_err(|e| format!("Unable to read {:?}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| format!("Unable to read {:?}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("There are no certificates in {:?}", path));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, String> {
+    let certs = load_certs(cert_path)?;
+    let file = std::fs::File::open(key_path)
+        .map_err(|e| format!("Unable to read {:?}: {}", key_path, e))?;
+    let key = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("Unable to read {:?}: {}", key_path, e))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("There is no private key in {:?}", key_path))?;
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|_| format!("The key in {:?} isn't supported", key_path))?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+#[test]
+fn maps_client_certificates_to_users() {
+    use rcgen::{Certificate as Generated, CertificateParams, DnType, SanType};
+
+    let mut params = CertificateParams::new(vec!["build.example.com".to_owned()]);
+    params.distinguished_name.push(DnType::CommonName, "alice");
+    params
+        .distinguished_name
+        .push(DnType::OrganizationalUnitName, "sre");
+    params
+        .subject_alt_names
+        .push(SanType::Rfc822Name("alice@example.com".to_owned()));
+    let generated = Generated::from_params(params).unwrap();
+
+    let client = ClientCertificate::from_der(&generated.serialize_der().unwrap()).unwrap();
+    let user = client.user(CertUsername::CommonName).unwrap();
+    assert_eq!("alice", user.username);
+    assert_eq!(vec!["sre".to_owned()], user.groups);
+    assert_eq!(
+        "alice@example.com",
+        client.user(CertUsername::Email).unwrap().username
+    );
+    assert_eq!(
+        "build.example.com",
+        client.user(CertUsername::Dns).unwrap().username
+    );
+    assert!(client.user(CertUsername::Uri).is_none());
+    assert!("subject".parse::<CertUsername>().is_err());
+
+    let dir = std::env::temp_dir();
+    let cert_path = dir.join(format!("gadget-tls-{}.crt", std::process::id()));
+    let key_path = dir.join(format!("gadget-tls-{}.key", std::process::id()));
+    std::fs::write(&cert_path, generated.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&key_path, generated.serialize_private_key_pem()).unwrap();
+    let cert = ReloadingCert::new(cert_path.clone(), key_path.clone()).unwrap();
+    std::fs::write(&key_path, "not a key").unwrap();
+    assert!(cert.reload().is_err());
+    std::fs::remove_file(&cert_path).unwrap();
+    std::fs::remove_file(&key_path).unwrap();
+}
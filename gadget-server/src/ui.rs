@@ -5,6 +5,8 @@ use tracing::{error, warn};
 use mime_guess::from_path;
 use warp::http::header::CONTENT_TYPE;
 
+use crate::handlers::RequestContext;
+
 #[derive(Clone)]
 pub struct WebDirectory {
     path: Arc<String>,
@@ -42,8 +44,14 @@ impl WebDirectory {
 
 pub async fn serve_embedded(
     path: warp::filters::path::Tail,
+    host: Option<String>,
     web_dir: Arc<WebDirectory>,
+    context: Arc<RequestContext<'_>>,
 ) -> Result<warp::reply::Response, std::convert::Infallible> {
+    // Hosts with a UI of their own get it instead of the server's.
+    let web_dir = context
+        .ui_directory(host.as_deref())
+        .unwrap_or_else(|| web_dir.as_ref());
     let mut path = path.as_str();
 
     if path.is_empty() {
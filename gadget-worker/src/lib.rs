@@ -1,6 +1,11 @@
 use crate::storage::WorkerStore;
 use gadget_lib::api::*;
-use gadget_lib::prelude::{AliasRedirect, GadgetLibError, Redirect, RedirectModel};
+use gadget_lib::miss::MissAction;
+use gadget_lib::negotiate::ResponseFormat;
+use gadget_lib::personal;
+use gadget_lib::prelude::{GadgetLibError, RedirectModel, RedirectStatus};
+use gadget_lib::resolve::MAX_RESOLVE_BATCH;
+use gadget_lib::suggest::DEFAULT_SUGGESTION_LIMIT;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use worker::kv::KvError;
@@ -32,6 +37,24 @@ struct StatusResponse {
 
 type Result<T> = std::result::Result<T, GadgetWorkerError>;
 
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    utils::set_panic_hook();
+
+    let swept = match WorkerStore::new(&env).await {
+        Ok(store) => store.sweep().await,
+        Err(e) => Err(e),
+    };
+    match swept {
+        Ok(history) => {
+            for entry in history {
+                console_log!("Redirect {}: {:?}", entry.redirect.alias, entry.event);
+            }
+        }
+        Err(e) => console_error!("Unable to sweep redirects: {}", e),
+    }
+}
+
 fn log_request(req: &Request) {
     console_log!(
         "{} - [{}], located at: {:?}, within: {}",
@@ -53,14 +76,28 @@ pub async fn main(req: Request, env: Env, _ctx: worker::Context) -> worker::Resu
     let router = Router::with_data(redirect_store);
 
     router
+        .get_async("/_api/history", handle_history)
+        .get_async("/_api/schedule", handle_schedule)
         .put_async("/_api/redirect/*id", handle_update)
         .post_async("/_api/redirect", handle_create)
+        .post_async("/_api/resolve", handle_resolve_batch)
         .delete_async("/_api/redirect/*path", handle_any_delete)
         .get_async("/*path", handle_any_get)
         .run(req, env)
         .await
 }
 
+/// Who the request is from, as vouched for by Cloudflare Access.
+fn request_user(req: &Request) -> Option<UserDetails> {
+    match req.headers().get("cf-access-authenticated-user-email") {
+        Ok(Some(email)) if !email.is_empty() => Some(UserDetails {
+            username: email,
+            groups: Vec::new(),
+        }),
+        _ => None,
+    }
+}
+
 fn extract_param(ctx: &RouteContext<WorkerStore>, param: &str) -> Option<String> {
     let mut id = match ctx.param(param) {
         None => return None,
@@ -75,7 +112,8 @@ fn extract_param(ctx: &RouteContext<WorkerStore>, param: &str) -> Option<String>
         return None;
     }
 
-    Some(id)
+    // Aliases in personal namespaces have their `/` encoded, like `~alice%2Fnotes`.
+    Some(id.replace("%2F", "/").replace("%2f", "/"))
 }
 
 async fn handle_create(
@@ -83,20 +121,42 @@ async fn handle_create(
     ctx: RouteContext<WorkerStore>,
 ) -> worker::Result<Response> {
     let redirect: ApiRedirect = req.json().await?;
+    let alias = match personal::owned_alias(&redirect.alias, request_user(&req).as_ref()) {
+        Ok(alias) => alias,
+        Err(e) => return worker::Response::error(e.to_string(), 403),
+    };
     let user = redirect
         .created_by
         .map(|x| x.username)
         .unwrap_or_else(|| "unknown".to_owned());
 
-    match ctx
-        .data
-        .create_redirect(&redirect.alias, &redirect.destination, &user)
-        .await
-    {
-        Ok(value) => Response::from_json(&value),
+    let created = if alias.is_empty() {
+        ctx.data
+            .create_short_code(&redirect.destination, &redirect.options, &user)
+            .await
+    } else {
+        ctx.data
+            .create_redirect(&alias, &redirect.destination, &redirect.options, &user)
+            .await
+    };
+
+    match created {
+        Ok(value) => {
+            let mut created = ApiRedirect::from(value);
+            created.short_url = ctx.data.short_url(&created.alias);
+            Response::from_json(&created)
+        }
         Err(GadgetWorkerError::GadgetLibError(GadgetLibError::RedirectDoesNotExists(_))) => {
             worker::Response::error("Not found", 404)
         }
+        Err(GadgetWorkerError::GadgetLibError(
+            e @ GadgetLibError::AliasLoop(_)
+            | e @ GadgetLibError::InvalidPattern(_)
+            | e @ GadgetLibError::InvalidSchedule(_),
+        )) => worker::Response::error(e.to_string(), 400),
+        Err(GadgetWorkerError::GadgetLibError(e @ GadgetLibError::RedirectExists(_))) => {
+            worker::Response::error(e.to_string(), 409)
+        }
         Err(e) => worker::Response::error(e.to_string(), 501),
     }
 }
@@ -112,6 +172,20 @@ async fn handle_update(
 
     console_log!("Updating id {}", id);
 
+    let id = match personal::owned_alias(&id, request_user(&req).as_ref()) {
+        Ok(id) => id,
+        Err(e) => return worker::Response::error(e.to_string(), 403),
+    };
+    // The id can be a public ref, so the owner is checked on the redirect it found.
+    let user = request_user(&req);
+    let id = match get_redirect(&id, user.as_ref(), &ctx.data).await {
+        Some(redirect) => match personal::owned_alias(&redirect.alias, user.as_ref()) {
+            Ok(_) => redirect.public_ref,
+            Err(e) => return worker::Response::error(e.to_string(), 403),
+        },
+        None => return worker::Response::error("Not found", 404),
+    };
+
     let redirect: UpdateRedirect = req.json().await?;
     let user = redirect
         .created_by
@@ -120,19 +194,24 @@ async fn handle_update(
 
     match ctx
         .data
-        .update_redirect(&id, &redirect.destination, &user)
+        .update_redirect(&id, &redirect.destination, redirect.options.as_ref(), &user)
         .await
     {
         Ok(value) => Response::from_json(&value),
         Err(GadgetWorkerError::GadgetLibError(GadgetLibError::RedirectDoesNotExists(_))) => {
             worker::Response::error("Not found", 404)
         }
+        Err(GadgetWorkerError::GadgetLibError(
+            e @ GadgetLibError::AliasLoop(_)
+            | e @ GadgetLibError::InvalidPattern(_)
+            | e @ GadgetLibError::InvalidSchedule(_),
+        )) => worker::Response::error(e.to_string(), 400),
         Err(e) => worker::Response::error(e.to_string(), 501),
     }
 }
 
 async fn handle_any_delete(
-    _req: Request,
+    req: Request,
     ctx: RouteContext<WorkerStore>,
 ) -> worker::Result<Response> {
     let path = match extract_param(&ctx, "path") {
@@ -149,7 +228,21 @@ async fn handle_any_delete(
         Some(value) => value,
     };
 
-    match ctx.data.delete_redirect(redirect_ref).await {
+    let redirect_ref = match personal::owned_alias(redirect_ref, request_user(&req).as_ref()) {
+        Ok(redirect_ref) => redirect_ref,
+        Err(e) => return worker::Response::error(e.to_string(), 403),
+    };
+    // The ref can be a public ref, so the owner is checked on the redirect it found.
+    let user = request_user(&req);
+    let redirect_ref = match get_redirect(&redirect_ref, user.as_ref(), &ctx.data).await {
+        Some(redirect) => match personal::owned_alias(&redirect.alias, user.as_ref()) {
+            Ok(_) => redirect.public_ref,
+            Err(e) => return worker::Response::error(e.to_string(), 403),
+        },
+        None => return worker::Response::error("Not found", 404),
+    };
+
+    match ctx.data.delete_redirect(&redirect_ref).await {
         Ok(_) => Response::from_json(&StatusResponse {
             status: "Deleted".to_owned(),
         }),
@@ -161,50 +254,200 @@ async fn handle_any_delete(
 }
 
 async fn handle_any_get(req: Request, ctx: RouteContext<WorkerStore>) -> worker::Result<Response> {
+    let user = request_user(&req);
+    let user = user.as_ref();
+
     if req.path() == "/_api/redirect" {
+        let include_personal = query_param(&req, "personal")? == "true";
         let resp = ctx
             .data
-            .get_all(0, 1000)
+            .get_all(0, 1000, user)
             .await
             .unwrap()
             .into_iter()
+            .filter(|x| personal::is_listed(&x.alias, user, include_personal))
             .map(ApiRedirect::from)
             .collect();
         return Response::from_json(&RedirectList { redirects: resp });
     }
 
     if req.path().starts_with("/_api/redirect/") {
-        match get_redirect(&req.path().replace("/_api/redirect/", ""), &ctx.data).await {
+        let alias = req
+            .path()
+            .replace("/_api/redirect/", "")
+            .replace("%2F", "/")
+            .replace("%2f", "/");
+        match get_redirect(&alias, user, &ctx.data).await {
             Some(redirect) => return worker::Response::from_json(&ApiRedirect::from(redirect)),
             None => return worker::Response::error("Not found", 404),
         }
     }
 
-    let path = req.path().replace('/', "").replace("%20", " ");
-    let redirect_ref: Vec<&str> = path.split(' ').collect();
-    let redirect_ref = match redirect_ref.first() {
-        None => {
-            return worker::Response::error("Not found", 404);
+    if req.path() == "/_api/resolve" {
+        let query = query_param(&req, "q")?;
+        return match ctx.data.resolve_link(&query, user).await {
+            Ok(Some(resolution)) => Response::from_json(&ResolvedRedirect::new(&query, resolution)),
+            Ok(None) => worker::Response::error("Not found", 404),
+            Err(GadgetWorkerError::GadgetLibError(e @ GadgetLibError::RedirectExpired(_))) => {
+                worker::Response::error(e.to_string(), 410)
+            }
+            Err(e) => worker::Response::error(e.to_string(), 508),
+        };
+    }
+
+    if req.path() == "/_api/suggest" {
+        let query = query_param(&req, "q")?;
+        let suggestions = ctx
+            .data
+            .suggest(&query, DEFAULT_SUGGESTION_LIMIT, user)
+            .await?;
+        return Response::from_json(&SuggestionList { query, suggestions });
+    }
+
+    let path = req.path().trim_start_matches('/').replace("%20", " ");
+    if path.is_empty() {
+        return worker::Response::error("Not found", 404);
+    }
+
+    console_debug!("Processing path {}", path);
+    match ctx.data.resolve_destination(&path, user).await {
+        Ok(Some(mut resolution)) => {
+            if let Some(chosen) = &resolution.chosen {
+                console_log!("{} picked {}", resolution.redirect.alias, chosen);
+            }
+            for redirect in &resolution.followed {
+                if !ctx.data.use_redirect(redirect).await? {
+                    return worker::Response::error(format!("{} has expired", redirect.alias), 410);
+                }
+            }
+            resolution.pass_through(req.url()?.query().unwrap_or_default(), None);
+            let accept = req.headers().get("accept")?;
+            let format = ResponseFormat::from_accept(accept.as_deref());
+            let resolved = ResolvedRedirect::new(&path, resolution);
+            match format {
+                ResponseFormat::Json => Response::from_json(&resolved),
+                _ => redirect_response(
+                    format,
+                    &resolved.destination,
+                    resolved.status,
+                    resolved.cache_control(),
+                ),
+            }
         }
-        Some(value) => value,
+        Ok(None) => handle_miss(&path, user, &ctx.data).await,
+        Err(GadgetWorkerError::GadgetLibError(e @ GadgetLibError::RedirectExpired(_))) => {
+            worker::Response::error(e.to_string(), 410)
+        }
+        Err(e) => worker::Response::error(e.to_string(), 508),
+    }
+}
+
+async fn handle_history(req: Request, ctx: RouteContext<WorkerStore>) -> worker::Result<Response> {
+    let history = ctx
+        .data
+        .get_history(0, 1000, request_user(&req).as_ref())
+        .await?;
+    Response::from_json(&HistoryList { history })
+}
+
+async fn handle_schedule(req: Request, ctx: RouteContext<WorkerStore>) -> worker::Result<Response> {
+    let changes = ctx
+        .data
+        .upcoming_changes(request_user(&req).as_ref())
+        .await?;
+    Response::from_json(&UpcomingChangeList { changes })
+}
+
+fn query_param(req: &Request, name: &str) -> worker::Result<String> {
+    Ok(req
+        .url()?
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+        .unwrap_or_default())
+}
+
+async fn handle_resolve_batch(
+    mut req: Request,
+    ctx: RouteContext<WorkerStore>,
+) -> worker::Result<Response> {
+    let batch: ResolveBatch = req.json().await?;
+    if batch.queries.len() > MAX_RESOLVE_BATCH {
+        return worker::Response::error(
+            format!(
+                "At most {} queries can be resolved at once",
+                MAX_RESOLVE_BATCH
+            ),
+            400,
+        );
+    }
+
+    let results = ctx
+        .data
+        .resolve_batch(batch.queries, request_user(&req).as_ref())
+        .await;
+    Response::from_json(&ResolveBatchResult { results })
+}
+
+/// A redirect to `destination`, which also names it in the body for [`ResponseFormat::Text`] clients.
+fn redirect_response(
+    format: ResponseFormat,
+    destination: &str,
+    status: RedirectStatus,
+    cache_control: Option<String>,
+) -> worker::Result<Response> {
+    let mut headers = Headers::new();
+    headers.set("location", worker::Url::parse(destination)?.as_str())?;
+    // The format depends on the Accept header, caches have to keep them apart.
+    headers.set("vary", "accept")?;
+    if let Some(cache_control) = cache_control {
+        headers.set("cache-control", &cache_control)?;
+    }
+
+    let response = match format {
+        ResponseFormat::Text => {
+            headers.set("content-type", "text/plain; charset=utf-8")?;
+            Response::ok(format!("{}\n", destination))?
+        }
+        _ => Response::empty()?,
     };
+    Ok(response.with_status(status.code()).with_headers(headers))
+}
 
-    console_debug!("Processing path {}", path);
-    match get_redirect(redirect_ref, &ctx.data).await {
-        Some(value) => {
-            let redirect = AliasRedirect::from(value);
-            worker::Response::redirect_with_status(
-                worker::Url::parse(&redirect.get_destination(&path))?,
-                307,
+async fn handle_miss(
+    path: &str,
+    user: Option<&UserDetails>,
+    store: &WorkerStore,
+) -> worker::Result<Response> {
+    match store.miss_action(path) {
+        MissAction::NotFound => worker::Response::error("Not found", 404),
+        MissAction::Suggest => {
+            let suggestions = store.suggest(path, DEFAULT_SUGGESTION_LIMIT, user).await?;
+            Ok(Response::from_json(&SuggestionList {
+                query: path.to_owned(),
+                suggestions,
+            })?
+            .with_status(404))
+        }
+        MissAction::Redirect(destination) => {
+            let status = RedirectStatus::default();
+            redirect_response(
+                ResponseFormat::Redirect,
+                &destination,
+                status,
+                status.cache_control(None),
             )
         }
-        None => worker::Response::error("Not found", 404),
     }
 }
 
-async fn get_redirect(path: &str, store: &WorkerStore) -> Option<RedirectModel> {
+async fn get_redirect(
+    path: &str,
+    user: Option<&UserDetails>,
+    store: &WorkerStore,
+) -> Option<RedirectModel> {
     console_debug!("Processing path {}", path);
-    match store.get_redirect(path).await {
+    match store.get_redirect(path, user).await {
         Ok(Some(value)) => Some(value),
         _ => None,
     }
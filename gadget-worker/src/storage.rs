@@ -1,16 +1,32 @@
 use crate::Result;
+use chrono::{NaiveDateTime, Utc};
+use gadget_lib::api::{ResolveBatchItem, UserDetails};
+use gadget_lib::miss::{MissAction, MissPolicy};
+use gadget_lib::personal;
 use gadget_lib::prelude::*;
+use gadget_lib::resolve::{resolve_batch, resolve_link, Resolution, DEFAULT_MAX_ALIAS_DEPTH};
+use gadget_lib::schedule::{upcoming_changes, UpcomingChange};
+use gadget_lib::shortcode::{ShortCodes, DEFAULT_SHORT_CODE_ALPHABET, DEFAULT_SHORT_CODE_LENGTH};
+use gadget_lib::suggest::{suggest_aliases, Suggestion};
 use serde::{Deserialize, Serialize};
 use worker::kv::KvStore;
 
 pub struct WorkerStore {
     store: KvStore,
     backend: InMemoryBackend,
+    miss_policy: MissPolicy,
+    short_codes: ShortCodes,
+    swept_at: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct KVStorageModel {
     redirects: Vec<RedirectModel>,
+    #[serde(default)]
+    history: Vec<HistoryEntry>,
+    /// When expired redirects and scheduled changes were last looked at.
+    #[serde(default)]
+    swept_at: Option<NaiveDateTime>,
 }
 
 impl WorkerStore {
@@ -20,20 +36,37 @@ impl WorkerStore {
             Some(value) => value,
             None => KVStorageModel {
                 redirects: Default::default(),
+                history: Default::default(),
+                swept_at: None,
             },
         };
 
-        let in_mem = InMemoryBackend::new(data.redirects);
+        let in_mem = InMemoryBackend::new(data.redirects).with_history(data.history);
+        for collision in in_mem.alias_collisions(DEFAULT_NAMESPACE)? {
+            worker::console_warn!("Aliases {} are the same alias", collision.join(", "));
+        }
 
         Ok(WorkerStore {
             backend: in_mem,
             store: kv,
+            miss_policy: load_miss_policy(env)?,
+            short_codes: load_short_codes(env)?,
+            swept_at: data.swept_at,
         })
     }
 
     async fn save(&self) -> Result<()> {
+        self.save_swept_at(self.swept_at).await
+    }
+
+    async fn save_swept_at(&self, swept_at: Option<NaiveDateTime>) -> Result<()> {
         let redirects = self.backend.get_internal_model().unwrap();
-        let stroage = KVStorageModel { redirects };
+        let history = self.backend.get_internal_history().unwrap();
+        let stroage = KVStorageModel {
+            redirects,
+            history,
+            swept_at,
+        };
 
         self.store.put("default", stroage)?.execute().await?;
 
@@ -42,43 +75,238 @@ impl WorkerStore {
 }
 
 impl WorkerStore {
-    pub async fn get_redirect(&self, redirect_ref: &str) -> Result<Option<RedirectModel>> {
-        Ok(self.backend.get_redirect(redirect_ref)?)
+    /// The redirect, unless `user` isn't allowed to see it. `~/` aliases are in `user`'s
+    /// personal namespace.
+    pub async fn get_redirect(
+        &self,
+        redirect_ref: &str,
+        user: Option<&UserDetails>,
+    ) -> Result<Option<RedirectModel>> {
+        let redirect_ref = match personal::expand(redirect_ref, user) {
+            Some(redirect_ref) => redirect_ref,
+            None => return Ok(None),
+        };
+        Ok(self
+            .backend
+            .get_redirect(DEFAULT_NAMESPACE, &redirect_ref)?
+            .filter(|redirect| redirect.visible_to(user)))
     }
 
     pub async fn create_redirect(
         &self,
         new_alias: &str,
         new_destination: &str,
+        options: &RedirectOptions,
         username: &str,
     ) -> Result<RedirectModel> {
-        let result = self
-            .backend
-            .create_redirect(new_alias, new_destination, username);
+        let result = self.backend.create_redirect(
+            DEFAULT_NAMESPACE,
+            new_alias,
+            new_destination,
+            options,
+            username,
+        );
+        self.save().await?;
+        Ok(result?)
+    }
+
+    /// Create a redirect with a generated alias.
+    pub async fn create_short_code(
+        &self,
+        new_destination: &str,
+        options: &RedirectOptions,
+        username: &str,
+    ) -> Result<RedirectModel> {
+        let result = self.short_codes.create_redirect(
+            &self.backend,
+            DEFAULT_NAMESPACE,
+            new_destination,
+            options,
+            username,
+        );
         self.save().await?;
         Ok(result?)
     }
 
+    pub fn short_url(&self, alias: &str) -> Option<String> {
+        self.short_codes.short_url(alias)
+    }
+
     pub async fn update_redirect(
         &self,
         redirect_ref: &str,
         new_dest: &str,
+        options: Option<&RedirectOptions>,
         username: &str,
     ) -> Result<RedirectModel> {
-        let result = self
-            .backend
-            .update_redirect(redirect_ref, new_dest, username);
+        let result = self.backend.update_redirect(
+            DEFAULT_NAMESPACE,
+            redirect_ref,
+            new_dest,
+            options,
+            username,
+        );
         self.save().await?;
         Ok(result?)
     }
 
     pub async fn delete_redirect(&self, redirect_ref: &str) -> Result<usize> {
-        let result = self.backend.delete_redirect(redirect_ref);
+        let result = self
+            .backend
+            .delete_redirect(DEFAULT_NAMESPACE, redirect_ref);
         self.save().await?;
         Ok(result?)
     }
 
-    pub async fn get_all(&self, page: u64, limit: usize) -> Result<Vec<RedirectModel>> {
-        Ok(self.backend.get_all(page, limit)?)
+    /// Count a use of the redirect, unless it has expired, and return whether it was used. Only
+    /// redirects with limited uses are saved right away, the others aren't worth a KV write on
+    /// every use.
+    pub async fn use_redirect(&self, redirect: &RedirectModel) -> Result<bool> {
+        let used = self.backend.use_redirect(
+            DEFAULT_NAMESPACE,
+            &redirect.public_ref,
+            Utc::now().naive_utc(),
+        )?;
+        if used && redirect.options.max_uses.is_some() {
+            self.save().await?;
+        }
+        Ok(used)
+    }
+
+    /// Move expired redirects into the history, and record the scheduled changes that took
+    /// over since the last sweep. Returns the new history entries.
+    pub async fn sweep(&self) -> Result<Vec<HistoryEntry>> {
+        let now = Utc::now().naive_utc();
+        let before = self.backend.get_internal_history()?.len();
+        self.backend.sweep_expired(DEFAULT_NAMESPACE, now)?;
+        self.backend.record_scheduled_changes(
+            DEFAULT_NAMESPACE,
+            self.swept_at.unwrap_or(now),
+            now,
+        )?;
+        self.save_swept_at(Some(now)).await?;
+
+        Ok(self.backend.get_internal_history()?.split_off(before))
+    }
+
+    pub async fn upcoming_changes(
+        &self,
+        user: Option<&UserDetails>,
+    ) -> Result<Vec<UpcomingChange>> {
+        let mut redirects = self.backend.get_internal_model()?;
+        redirects.retain(|redirect| redirect.visible_to(user));
+        Ok(upcoming_changes(&redirects, Utc::now().naive_utc()))
+    }
+
+    pub async fn get_history(
+        &self,
+        page: u64,
+        limit: usize,
+        user: Option<&UserDetails>,
+    ) -> Result<Vec<HistoryEntry>> {
+        let mut history = self.backend.get_history(DEFAULT_NAMESPACE, page, limit)?;
+        history.retain(|entry| entry.redirect.visible_to(user));
+        Ok(history)
+    }
+
+    pub async fn resolve_destination(
+        &self,
+        query: &str,
+        user: Option<&UserDetails>,
+    ) -> Result<Option<Resolution>> {
+        Ok(resolve_destination(
+            &self.backend,
+            DEFAULT_NAMESPACE,
+            query,
+            DEFAULT_MAX_ALIAS_DEPTH,
+            user,
+        )?)
+    }
+
+    pub async fn resolve_link(
+        &self,
+        link: &str,
+        user: Option<&UserDetails>,
+    ) -> Result<Option<Resolution>> {
+        Ok(resolve_link(
+            &self.backend,
+            DEFAULT_NAMESPACE,
+            link,
+            DEFAULT_MAX_ALIAS_DEPTH,
+            user,
+        )?)
+    }
+
+    pub async fn resolve_batch(
+        &self,
+        queries: Vec<String>,
+        user: Option<&UserDetails>,
+    ) -> Vec<ResolveBatchItem> {
+        resolve_batch(
+            &self.backend,
+            DEFAULT_NAMESPACE,
+            queries,
+            DEFAULT_MAX_ALIAS_DEPTH,
+            user,
+        )
+    }
+
+    pub async fn suggest(
+        &self,
+        query: &str,
+        limit: usize,
+        user: Option<&UserDetails>,
+    ) -> Result<Vec<Suggestion>> {
+        Ok(suggest_aliases(
+            &self.backend,
+            DEFAULT_NAMESPACE,
+            query,
+            limit,
+            user,
+        )?)
+    }
+
+    pub fn miss_action(&self, query: &str) -> MissAction {
+        self.miss_policy.action(query)
+    }
+
+    pub async fn get_all(
+        &self,
+        page: u64,
+        limit: usize,
+        user: Option<&UserDetails>,
+    ) -> Result<Vec<RedirectModel>> {
+        let mut redirects = self.backend.get_all(DEFAULT_NAMESPACE, page, limit)?;
+        redirects.retain(|redirect| redirect.visible_to(user));
+        Ok(redirects)
+    }
+}
+
+fn load_miss_policy(env: &worker::Env) -> Result<MissPolicy> {
+    match env.var("MISS_POLICY") {
+        Ok(policy) => Ok(policy.to_string().parse()?),
+        Err(_) => Ok(Default::default()),
+    }
+}
+
+fn load_short_codes(env: &worker::Env) -> Result<ShortCodes> {
+    let length = match env.var("SHORT_CODE_LENGTH") {
+        Ok(length) => {
+            let length = length.to_string();
+            length.parse().map_err(|_| {
+                GadgetLibError::InvalidShortCode(format!("length {} isn't a number", length))
+            })?
+        }
+        Err(_) => DEFAULT_SHORT_CODE_LENGTH,
+    };
+    let alphabet = match env.var("SHORT_CODE_ALPHABET") {
+        Ok(alphabet) => alphabet.to_string(),
+        Err(_) => DEFAULT_SHORT_CODE_ALPHABET.to_owned(),
+    };
+
+    let mut short_codes = ShortCodes::new(length, &alphabet)?;
+    if let Ok(host) = env.var("SHORT_URL_HOST") {
+        short_codes = short_codes.with_host(&host.to_string());
     }
+    Ok(short_codes)
 }